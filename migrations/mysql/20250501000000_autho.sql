@@ -0,0 +1,12 @@
+CREATE TABLE autho_user (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    email VARCHAR(255) NOT NULL UNIQUE,
+    hashed_password VARCHAR(255)
+);
+
+CREATE TABLE autho_session (
+    id BINARY(16) PRIMARY KEY,
+    user_id BIGINT,
+    data JSON NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES autho_user (id) ON DELETE CASCADE
+);
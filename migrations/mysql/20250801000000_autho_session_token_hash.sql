@@ -0,0 +1,4 @@
+-- Session ids are now hashes of the session token,
+-- so existing sessions can no longer be found.
+DELETE FROM autho_session;
+ALTER TABLE autho_session MODIFY id BINARY(32);
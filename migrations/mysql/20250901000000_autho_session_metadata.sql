@@ -0,0 +1,4 @@
+ALTER TABLE autho_session ADD COLUMN user_agent TEXT;
+ALTER TABLE autho_session ADD COLUMN ip_address VARCHAR(45);
+ALTER TABLE autho_session ADD COLUMN created_at BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE autho_session ADD COLUMN last_active_at BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE autho_session ADD COLUMN impersonator_id BIGINT;
+ALTER TABLE autho_session ADD FOREIGN KEY (impersonator_id) REFERENCES autho_user (id) ON DELETE CASCADE;
@@ -0,0 +1,6 @@
+CREATE TABLE autho_password_history (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    user_id BIGINT NOT NULL,
+    hashed_password VARCHAR(255) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES autho_user (id) ON DELETE CASCADE
+);
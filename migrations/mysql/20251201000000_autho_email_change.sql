@@ -0,0 +1,9 @@
+CREATE TABLE autho_email_change (
+    hash BINARY(32) PRIMARY KEY,
+    cancel_hash BINARY(32) NOT NULL UNIQUE,
+    user_id BIGINT NOT NULL,
+    old_email VARCHAR(255) NOT NULL,
+    new_email VARCHAR(255) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES autho_user (id) ON DELETE CASCADE
+);
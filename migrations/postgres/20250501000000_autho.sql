@@ -0,0 +1,11 @@
+CREATE TABLE autho_user (
+    id BIGSERIAL PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    hashed_password TEXT
+);
+
+CREATE TABLE autho_session (
+    id UUID PRIMARY KEY,
+    user_id BIGINT REFERENCES autho_user (id) ON DELETE CASCADE,
+    data JSONB NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE autho_session ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1 @@
+ALTER TABLE autho_session ADD COLUMN impersonator_id BIGINT REFERENCES autho_user (id) ON DELETE CASCADE;
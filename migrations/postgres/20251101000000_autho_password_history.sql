@@ -0,0 +1,5 @@
+CREATE TABLE autho_password_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES autho_user (id) ON DELETE CASCADE,
+    hashed_password TEXT NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE autho_email_change (
+    hash BYTEA PRIMARY KEY,
+    cancel_hash BYTEA NOT NULL UNIQUE,
+    user_id BIGINT NOT NULL REFERENCES autho_user (id) ON DELETE CASCADE,
+    old_email TEXT NOT NULL,
+    new_email TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
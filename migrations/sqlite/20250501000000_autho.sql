@@ -0,0 +1,11 @@
+CREATE TABLE autho_user (
+    id INTEGER PRIMARY KEY,
+    email TEXT NOT NULL UNIQUE,
+    hashed_password TEXT
+);
+
+CREATE TABLE autho_session (
+    id BLOB PRIMARY KEY,
+    user_id INTEGER REFERENCES autho_user (id) ON DELETE CASCADE,
+    data TEXT NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE autho_session ADD COLUMN authenticated_at BIGINT;
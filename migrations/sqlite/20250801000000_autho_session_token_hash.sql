@@ -0,0 +1,3 @@
+-- Session ids are now hashes of the session token,
+-- so existing sessions can no longer be found.
+DELETE FROM autho_session;
@@ -0,0 +1,5 @@
+CREATE TABLE autho_password_history (
+    id INTEGER PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES autho_user (id) ON DELETE CASCADE,
+    hashed_password TEXT NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE autho_email_change (
+    hash BLOB PRIMARY KEY,
+    cancel_hash BLOB NOT NULL UNIQUE,
+    user_id INTEGER NOT NULL REFERENCES autho_user (id) ON DELETE CASCADE,
+    old_email TEXT NOT NULL,
+    new_email TEXT NOT NULL,
+    expires_at BIGINT NOT NULL
+);
@@ -0,0 +1,431 @@
+use std::time::{Duration, SystemTime};
+
+use crate::token_utils::{random_token, sha256};
+use crate::{
+    Backend, ComposedBackend, ComposedError, Session, SessionStore, User,
+    UserStore,
+};
+
+/// The number of random bytes in an API token.
+const API_TOKEN_BYTES: usize = 32;
+
+/// A personal access token, used to authenticate API requests.
+///
+/// The token itself is never stored by the backend, only its hash.
+/// This means it can only be shown to the user once, right after creation.
+pub struct ApiToken(String);
+
+impl ApiToken {
+    /// Generate a new random token starting with `prefix`.
+    fn generate(prefix: &str) -> Self {
+        Self(random_token::<API_TOKEN_BYTES>(prefix))
+    }
+
+    /// Get the token as a string.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the hash of this token, as stored by the backend.
+    pub fn hash(&self) -> ApiTokenHash {
+        ApiTokenHash::new(&self.0)
+    }
+}
+
+impl std::fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ApiToken([...])")
+    }
+}
+
+/// The SHA-256 hash of an [`ApiToken`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ApiTokenHash(pub [u8; 32]);
+
+impl ApiTokenHash {
+    fn new(token: &str) -> Self {
+        Self(sha256(token))
+    }
+}
+
+/// An API token as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct ApiTokenRecord<UserId> {
+    /// The hash of the token.
+    pub hash: ApiTokenHash,
+    /// The user the token authenticates as.
+    pub user_id: UserId,
+    /// A name chosen by the user to recognize the token.
+    pub name: String,
+    /// The scopes the token grants access to.
+    pub scopes: Vec<String>,
+    /// When the token was created.
+    pub created_at: SystemTime,
+    /// When the token expires, if ever.
+    pub expires_at: Option<SystemTime>,
+    /// When the token was last used to authenticate.
+    pub last_used_at: Option<SystemTime>,
+    /// Whether the token was revoked.
+    pub revoked: bool,
+}
+
+impl<UserId> ApiTokenRecord<UserId> {
+    /// Whether the token can be used to authenticate at the given time.
+    pub fn is_valid_at(&self, now: SystemTime) -> bool {
+        !self.revoked && self.expires_at.is_none_or(|expires| now < expires)
+    }
+
+    /// Whether the token grants access to a scope.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+/// The interface for a backend that supports API tokens.
+pub trait ApiTokenBackend: Backend {
+    /// Get the prefix of newly generated API tokens.
+    ///
+    /// A recognizable prefix helps secret scanners to detect leaked tokens.
+    fn api_token_prefix(&self) -> &str {
+        "autho_pat_"
+    }
+
+    /// Store a new API token.
+    fn store_api_token(
+        &self,
+        token: &ApiTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load an API token by its hash.
+    fn load_api_token(
+        &self,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<Option<ApiTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Load all API tokens of a user, including revoked and expired tokens.
+    fn list_api_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<ApiTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Record when an API token was last used.
+    fn update_api_token_last_used(
+        &self,
+        hash: &ApiTokenHash,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Revoke an API token of a user.
+    ///
+    /// If the token belongs to another user, this must do nothing.
+    fn revoke_api_token(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that supports API tokens.
+///
+/// See [`ApiTokenBackend`].
+pub trait ApiTokenStore: UserStore {
+    /// Get the prefix of newly generated API tokens.
+    fn api_token_prefix(&self) -> &str {
+        "autho_pat_"
+    }
+
+    /// Store a new API token.
+    fn store_api_token(
+        &self,
+        token: &ApiTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load an API token by its hash.
+    fn load_api_token(
+        &self,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<Option<ApiTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Load all API tokens of a user, including revoked and expired tokens.
+    fn list_api_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<ApiTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Record when an API token was last used.
+    fn update_api_token_last_used(
+        &self,
+        hash: &ApiTokenHash,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Revoke an API token of a user.
+    ///
+    /// See [`ApiTokenBackend::revoke_api_token()`].
+    fn revoke_api_token(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> ApiTokenBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: ApiTokenStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn api_token_prefix(&self) -> &str {
+        self.users.api_token_prefix()
+    }
+
+    fn store_api_token(
+        &self,
+        token: &ApiTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_api_token(token);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_api_token(
+        &self,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<Option<ApiTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.load_api_token(hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn list_api_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<ApiTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.list_api_tokens(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn update_api_token_last_used(
+        &self,
+        hash: &ApiTokenHash,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.update_api_token_last_used(hash, last_used_at);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn revoke_api_token(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.revoke_api_token(user_id, hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// The reason an API token could not be created.
+///
+/// See [`create_api_token()`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ApiTokenError {
+    /// No user is logged into the session.
+    NotAuthenticated,
+    /// A user is being [impersonated](Session::impersonate) in the session.
+    Impersonating,
+}
+
+impl std::fmt::Display for ApiTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotAuthenticated => "not authenticated",
+            Self::Impersonating => "impersonating a user",
+        })
+    }
+}
+
+impl std::error::Error for ApiTokenError {}
+
+/// Create a new API token for the user logged into the session.
+///
+/// This returns the token itself, which should be shown to the user once,
+/// together with the record that was stored by the backend.
+/// This fails while [impersonating](Session::impersonate) a user.
+pub async fn create_api_token<B: ApiTokenBackend>(
+    session: &Session<B>,
+    name: String,
+    scopes: Vec<String>,
+    expires_in: Option<Duration>,
+) -> Result<
+    Result<(ApiToken, ApiTokenRecord<<B::User as User>::Id>), ApiTokenError>,
+    B::Error,
+> {
+    if session.impersonator().is_some() {
+        return Ok(Err(ApiTokenError::Impersonating));
+    }
+    let Some(user_id) = session.user.id().cloned() else {
+        return Ok(Err(ApiTokenError::NotAuthenticated));
+    };
+    let backend = &session.backend;
+    let token = ApiToken::generate(backend.api_token_prefix());
+    let now = SystemTime::now();
+    let record = ApiTokenRecord {
+        hash: token.hash(),
+        user_id,
+        name,
+        scopes,
+        created_at: now,
+        expires_at: expires_in.map(|duration| now + duration),
+        last_used_at: None,
+        revoked: false,
+    };
+    backend.store_api_token(&record).await?;
+    Ok(Ok((token, record)))
+}
+
+/// Authenticate a user by an API token.
+///
+/// This returns `None` if the token is unknown, revoked or expired,
+/// or if the user it belongs to does not exist (anymore)
+/// or is not [active](crate::AccountStatus::is_active).
+/// On success, the time the token was last used is updated.
+pub async fn authenticate_api_token<B: ApiTokenBackend>(
+    backend: &B,
+    token: &str,
+) -> Result<Option<(B::User, ApiTokenRecord<<B::User as User>::Id>)>, B::Error>
+{
+    if !token.starts_with(backend.api_token_prefix()) {
+        return Ok(None);
+    }
+    let hash = ApiTokenHash::new(token);
+    let Some(mut record) = backend.load_api_token(&hash).await? else {
+        return Ok(None);
+    };
+    let now = SystemTime::now();
+    if !record.is_valid_at(now) {
+        return Ok(None);
+    }
+    let Some(user) = backend.load_user(&record.user_id).await? else {
+        return Ok(None);
+    };
+    if !user.account_status().is_active() {
+        return Ok(None);
+    }
+    backend.update_api_token_last_used(&hash, now).await?;
+    record.last_used_at = Some(now);
+    Ok(Some((user, record)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountStatus;
+    use crate::testing::TestBackend;
+
+    async fn create(
+        backend: &TestBackend,
+        user_id: u64,
+        expires_in: Option<Duration>,
+    ) -> (ApiToken, ApiTokenRecord<u64>) {
+        let session = backend.session(Some(user_id));
+        let scopes = vec!["read".to_owned()];
+        create_api_token(&session, "token".to_owned(), scopes, expires_in)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn authenticate(
+        backend: &TestBackend,
+        token: &str,
+    ) -> Option<ApiTokenRecord<u64>> {
+        let result = authenticate_api_token(backend, token).await.unwrap();
+        result.map(|(user, record)| {
+            assert_eq!(user.id, record.user_id);
+            record
+        })
+    }
+
+    #[tokio::test]
+    async fn create_and_authenticate() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let (token, record) = create(&backend, user_id, None).await;
+        assert!(token.as_str().starts_with("autho_pat_"));
+        assert_eq!(record.hash, token.hash());
+        assert_eq!(record.user_id, user_id);
+        let tokens = backend.list_api_tokens(&user_id).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_none());
+
+        let record = authenticate(&backend, token.as_str()).await.unwrap();
+        assert!(record.has_scope("read"));
+        assert!(!record.has_scope("write"));
+        let tokens = backend.list_api_tokens(&user_id).await.unwrap();
+        assert_eq!(tokens[0].last_used_at, record.last_used_at);
+        assert!(record.last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn create_not_authenticated() {
+        let backend = TestBackend::default();
+        let session = backend.session(None);
+        let result =
+            create_api_token(&session, "token".to_owned(), Vec::new(), None)
+                .await
+                .unwrap();
+        assert_eq!(result.unwrap_err(), ApiTokenError::NotAuthenticated);
+    }
+
+    #[tokio::test]
+    async fn authenticate_unknown() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let (token, _) = create(&backend, user_id, None).await;
+        let unknown = format!("autho_pat_{}", "0".repeat(64));
+        assert!(authenticate(&backend, &unknown).await.is_none());
+        let (_, secret) = token.as_str().split_at("autho_pat_".len());
+        assert!(authenticate(&backend, secret).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn authenticate_expired() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let (token, _) = create(&backend, user_id, Some(Duration::ZERO)).await;
+        assert!(authenticate(&backend, token.as_str()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn revoke() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let other_user_id = backend.add_user("b@example.com");
+        let (token, record) = create(&backend, user_id, None).await;
+
+        // Users cannot revoke tokens of other users.
+        backend
+            .revoke_api_token(&other_user_id, &record.hash)
+            .await
+            .unwrap();
+        assert!(authenticate(&backend, token.as_str()).await.is_some());
+
+        backend
+            .revoke_api_token(&user_id, &record.hash)
+            .await
+            .unwrap();
+        assert!(authenticate(&backend, token.as_str()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn authenticate_inactive_user() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let (token, _) = create(&backend, user_id, None).await;
+        backend.state().users[0].status = AccountStatus::PendingDeletion;
+        assert!(authenticate(&backend, token.as_str()).await.is_none());
+    }
+}
@@ -0,0 +1,38 @@
+/// A security-relevant event, reported to the backend for auditing.
+///
+/// See [`Backend::record_audit_event()`](crate::Backend::record_audit_event).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum AuditEvent<UserId> {
+    /// A user started impersonating another user.
+    ImpersonationStarted {
+        /// The user impersonating the other user.
+        impersonator: UserId,
+        /// The user being impersonated.
+        user: UserId,
+    },
+    /// A user stopped impersonating another user.
+    ImpersonationStopped {
+        /// The user that was impersonating the other user.
+        impersonator: UserId,
+        /// The user that was impersonated.
+        user: UserId,
+    },
+    /// A passkey was used with a signature counter that did not increase,
+    /// so the authenticator may have been cloned.
+    PasskeyCloneDetected {
+        /// The user the passkey belongs to.
+        user: UserId,
+        /// The id of the passkey.
+        credential_id: Vec<u8>,
+    },
+    /// A user changed their email address.
+    EmailChanged {
+        /// The user whose email address changed.
+        user: UserId,
+        /// The previous email address.
+        old_email: String,
+        /// The new email address.
+        new_email: String,
+    },
+}
@@ -1,34 +1,80 @@
 use axum::{
     extract::{FromRef, FromRequestParts},
-    http::request::Parts,
-    response::IntoResponse,
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
 };
 use axum_extra::extract::CookieJar;
+use axum_extra::extract::cookie::{Cookie, SameSite};
 
-use crate::{Backend, CookieSessionBackend, Session, SessionId};
+use crate::{
+    ApiTokenBackend, ApiTokenRecord, Backend, ComposedError,
+    CookieSessionBackend, ReauthenticationRequired, RememberMeBackend,
+    RememberMeLogin, RememberMeToken, SaveError, Session, SessionFields,
+    SessionMetadata, SessionToken, User, UserSessionsBackend,
+};
 
-pub fn get_session_id(
+pub fn get_session_token(
     cookie_name: &str,
     parts: &mut Parts,
-) -> Option<SessionId> {
+) -> Option<SessionToken> {
     let cookies = CookieJar::from_headers(&parts.headers);
     cookies.get(cookie_name)?.value().parse().ok()
 }
 
+/// Build the cookie identifying a session.
+///
+/// The cookie is sent along with top-level navigations from other sites,
+/// such as the redirect back from an identity provider.
+/// This returns `None` if the session has not been stored,
+/// so call this after saving the session,
+/// see [`Session::is_stored()`].
+pub fn session_cookie<B: CookieSessionBackend>(
+    session: &Session<B>,
+) -> Option<Cookie<'static>> {
+    if !session.is_stored() {
+        return None;
+    }
+    let cookie = Cookie::build((
+        session.backend.session_cookie_name().to_owned(),
+        session.token().as_str().to_owned(),
+    ))
+    .path("/")
+    .http_only(true)
+    .secure(true)
+    .same_site(SameSite::Lax)
+    .build();
+    Some(cookie)
+}
+
+/// Get the user agent and IP address of the client.
+///
+/// The IP address is only available when the app is served
+/// with [`ConnectInfo`](axum::extract::ConnectInfo),
+/// and is the address of the peer, such as a reverse proxy.
+pub fn get_client(parts: &Parts) -> (Option<String>, Option<std::net::IpAddr>) {
+    let user_agent = parts
+        .headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let ip_address = parts
+        .extensions
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|info| info.0.ip());
+    (user_agent, ip_address)
+}
+
 pub async fn load_session<B: Backend + CookieSessionBackend>(
     backend: B,
     parts: &mut Parts,
 ) -> Result<Result<Session<B>, B>, B::Error> {
-    if let Some(session_id) =
-        get_session_id(backend.session_cookie_name(), parts)
+    if let Some(session_token) =
+        get_session_token(backend.session_cookie_name(), parts)
     {
-        if let Some(fields) = backend.load_session_data(&session_id).await? {
-            Ok(Ok(Session::new(
-                backend,
-                session_id,
-                fields.user_id,
-                fields.data,
-            )))
+        if let Some(fields) =
+            backend.load_session_data(&session_token.id()).await?
+        {
+            Ok(Ok(Session::new(backend, session_token, fields)))
         } else {
             // NOTE: We renew the session id to ensure
             // users cannot choose their own session id.
@@ -53,18 +99,379 @@ where
         state: &S,
     ) -> Result<Self, Self::Rejection> {
         let backend: B = FromRef::from_ref(state);
-        match load_session(backend, parts).await? {
+        let mut session = match load_session(backend, parts).await? {
             Ok(session) => {
                 // This user has an existing session,
-                Ok(session)
+                session
             }
             Err(backend) => {
                 // Session id not set or session does not exist (anymore).
-                let session_id = SessionId::new();
-                let user_id = None;
-                let data = backend.create_session_data().await?;
-                Ok(Session::new(backend, session_id, user_id, data))
+                // NOTE: The new session is only stored once it is saved.
+                let session_token =
+                    SessionToken::generate(backend.session_token_bytes());
+                let fields = SessionFields {
+                    user_id: None,
+                    data: backend.create_session_data().await?,
+                    version: 0,
+                    authenticated_at: None,
+                    impersonator: None,
+                    metadata: SessionMetadata::new(),
+                };
+                Session::new(backend, session_token, fields)
             }
+        };
+        let (user_agent, ip_address) = get_client(parts);
+        session.set_client(user_agent, ip_address);
+        session.touch().await?;
+        Ok(session)
+    }
+}
+
+/// Build the cookie holding a remember-me token.
+///
+/// The cookie expires when the token does,
+/// see [`RememberMeBackend::remember_me_ttl()`].
+pub fn remember_me_cookie<B: RememberMeBackend>(
+    backend: &B,
+    token: &RememberMeToken,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::build((
+        backend.remember_me_cookie_name().to_owned(),
+        token.as_str().to_owned(),
+    ))
+    .path("/")
+    .http_only(true)
+    .secure(true)
+    .same_site(SameSite::Lax);
+    if let Ok(max_age) = backend.remember_me_ttl().try_into() {
+        cookie = cookie.max_age(max_age);
+    }
+    cookie.build()
+}
+
+/// A session, with the user logged in by a remember-me cookie
+/// if there was no user logged in yet.
+///
+/// Use this instead of [`Session`] to restore logins of returning users,
+/// see [`login_by_remember_me()`](crate::login_by_remember_me).
+/// When a user was logged in, the session is saved,
+/// and the handler must return `cookies`
+/// to send the new session cookie and the rotated remember-me token.
+/// An invalid remember-me cookie is removed.
+pub struct RememberedSession<B: Backend> {
+    /// The session.
+    pub session: Session<B>,
+    /// The cookies to send to the client.
+    pub cookies: CookieJar,
+}
+
+impl<B, S> FromRequestParts<S> for RememberedSession<B>
+where
+    B: RememberMeBackend + UserSessionsBackend + CookieSessionBackend + Sync,
+    B: FromRef<S>,
+    B::Error: IntoResponse,
+    S: Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let mut session = Session::<B>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let mut cookies = CookieJar::new();
+        let user = session.user_mut().await;
+        if user.map_err(IntoResponse::into_response)?.is_some() {
+            return Ok(Self { session, cookies });
+        }
+        let name = session.backend.remember_me_cookie_name().to_owned();
+        let request_cookies = CookieJar::from_headers(&parts.headers);
+        let Some(cookie) = request_cookies.get(&name) else {
+            return Ok(Self { session, cookies });
+        };
+        match crate::login_by_remember_me(&mut session, cookie.value())
+            .await
+            .map_err(IntoResponse::into_response)?
+        {
+            Some(login) => {
+                session.save().await.map_err(IntoResponse::into_response)?;
+                if let RememberMeLogin::Rotated(token) = login {
+                    cookies = cookies
+                        .add(remember_me_cookie(&session.backend, &token));
+                }
+                if let Some(cookie) = session_cookie(&session) {
+                    cookies = cookies.add(cookie);
+                }
+            }
+            None => {
+                cookies = cookies.remove(Cookie::build(name).path("/"));
+            }
+        }
+        Ok(Self { session, cookies })
+    }
+}
+
+impl<S, U> IntoResponse for ComposedError<S, U>
+where
+    S: IntoResponse,
+    U: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        match self {
+            Self::Session(e) => e.into_response(),
+            Self::User(e) => e.into_response(),
+        }
+    }
+}
+
+impl<E: IntoResponse> IntoResponse for SaveError<E> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Backend(e) => e.into_response(),
+            Self::Conflict => StatusCode::CONFLICT.into_response(),
         }
     }
 }
+
+impl IntoResponse for ReauthenticationRequired {
+    fn into_response(self) -> Response {
+        StatusCode::FORBIDDEN.into_response()
+    }
+}
+
+/// The configuration of the [`RecentlyAuthenticated`] extractor.
+#[derive(Clone, Debug)]
+pub struct RecentAuthConfig {
+    /// How long ago the user may have last authenticated.
+    pub max_age: std::time::Duration,
+    /// Where to redirect the user to authenticate again.
+    ///
+    /// If not set, the request is rejected with `403 Forbidden`.
+    pub redirect_to: Option<String>,
+}
+
+/// A session where the user authenticated recently.
+///
+/// Use this instead of [`Session`] to guard sensitive actions,
+/// see [`Session::require_recent_auth()`].
+/// The maximum age is configured through [`RecentAuthConfig`].
+pub struct RecentlyAuthenticated<B: Backend>(pub Session<B>);
+
+impl<B, S> FromRequestParts<S> for RecentlyAuthenticated<B>
+where
+    B: CookieSessionBackend,
+    B: FromRef<S>,
+    B::Error: IntoResponse,
+    RecentAuthConfig: FromRef<S>,
+    S: Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = RecentAuthConfig::from_ref(state);
+        let mut session = Session::<B>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        match session
+            .require_recent_auth(config.max_age)
+            .await
+            .map_err(IntoResponse::into_response)?
+        {
+            Ok(()) => Ok(Self(session)),
+            Err(e) => Err(match config.redirect_to {
+                Some(url) => axum::response::Redirect::to(&url).into_response(),
+                None => e.into_response(),
+            }),
+        }
+    }
+}
+
+/// Get the token from an `Authorization: Bearer` header.
+pub fn get_bearer_token(parts: &Parts) -> Option<&str> {
+    let value = parts.headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// A user authenticated by an API token
+/// in the `Authorization: Bearer` header.
+pub struct ApiTokenUser<B: Backend> {
+    /// The authenticated user.
+    pub user: B::User,
+    /// The token used to authenticate.
+    pub token: ApiTokenRecord<<B::User as User>::Id>,
+}
+
+/// The rejection of extractors authenticating by a bearer token.
+#[derive(Debug)]
+pub enum BearerRejection<E> {
+    /// The token is missing or invalid.
+    Unauthorized,
+    /// The backend returned an error.
+    Backend(E),
+}
+
+impl<E: IntoResponse> IntoResponse for BearerRejection<E> {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+            )
+                .into_response(),
+            Self::Backend(e) => e.into_response(),
+        }
+    }
+}
+
+impl<B, S> FromRequestParts<S> for ApiTokenUser<B>
+where
+    B: ApiTokenBackend + Sync,
+    B: FromRef<S>,
+    B::Error: IntoResponse,
+    S: Sync,
+{
+    type Rejection = BearerRejection<B::Error>;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let backend: B = FromRef::from_ref(state);
+        let Some(token) = get_bearer_token(parts) else {
+            return Err(BearerRejection::Unauthorized);
+        };
+        match crate::authenticate_api_token(&backend, token).await {
+            Ok(Some((user, token))) => Ok(Self { user, token }),
+            Ok(None) => Err(BearerRejection::Unauthorized),
+            Err(e) => Err(BearerRejection::Backend(e)),
+        }
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl<Id, S> FromRequestParts<S> for crate::jwt::AccessToken<Id>
+where
+    Id: std::str::FromStr,
+    crate::jwt::JwtConfig: FromRef<S>,
+    S: Sync,
+{
+    type Rejection = BearerRejection<std::convert::Infallible>;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = crate::jwt::JwtConfig::from_ref(state);
+        get_bearer_token(parts)
+            .and_then(|token| config.validate_access_token(token))
+            .ok_or(BearerRejection::Unauthorized)
+    }
+}
+
+#[cfg(feature = "oidc")]
+impl<E: IntoResponse> IntoResponse for crate::oidc::Error<E> {
+    fn into_response(self) -> Response {
+        use crate::oidc::Error;
+        match self {
+            Error::Backend(e) => e.into_response(),
+            Error::Http(_)
+            | Error::InvalidResponse(_)
+            | Error::InvalidIdToken(_) => {
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+            Error::Provider(_) | Error::InvalidState => {
+                StatusCode::BAD_REQUEST.into_response()
+            }
+        }
+    }
+}
+
+/// Redirect to an identity provider to log in.
+///
+/// This handler starts the login, saves the session
+/// and sets the session cookie.
+/// The identity provider redirects back to a route using [`OidcCallback`].
+#[cfg(feature = "oidc")]
+pub async fn oidc_login<B>(
+    mut session: Session<B>,
+    axum::extract::State(provider): axum::extract::State<
+        crate::oidc::OidcProvider,
+    >,
+) -> Result<(CookieJar, axum::response::Redirect), Response>
+where
+    B: CookieSessionBackend,
+    B::SessionData: crate::oidc::OidcSessionData,
+    B::Error: IntoResponse,
+{
+    let url = crate::oidc::start_login(&mut session, &provider)
+        .map_err(IntoResponse::into_response)?;
+    session.save().await.map_err(IntoResponse::into_response)?;
+    let mut cookies = CookieJar::new();
+    if let Some(cookie) = session_cookie(&session) {
+        cookies = cookies.add(cookie);
+    }
+    Ok((cookies, axum::response::Redirect::to(&url)))
+}
+
+/// The redirect back from an identity provider.
+///
+/// This extractor finishes the login started by [`oidc_login()`]
+/// and saves the session.
+/// Since logging in replaces the session token,
+/// the handler must send the [`session_cookie()`].
+/// The handler decides what to do next,
+/// for example registering a new user for an unlinked identity.
+#[cfg(feature = "oidc")]
+pub struct OidcCallback<B: Backend> {
+    /// The session, with the user logged in if the identity is linked.
+    pub session: Session<B>,
+    /// The result of the login.
+    pub login: crate::oidc::OidcLogin,
+}
+
+#[cfg(feature = "oidc")]
+impl<B, S> FromRequestParts<S> for OidcCallback<B>
+where
+    B: crate::oidc::OidcBackend + CookieSessionBackend,
+    B: FromRef<S>,
+    B::SessionData: crate::oidc::OidcSessionData,
+    B::Error: IntoResponse,
+    crate::oidc::OidcProvider: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(params) = axum::extract::Query::<
+            crate::oidc::CallbackParams,
+        >::from_request_parts(
+            parts, state
+        )
+        .await
+        .map_err(IntoResponse::into_response)?;
+        let provider = crate::oidc::OidcProvider::from_ref(state);
+        let mut session = Session::<B>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let login =
+            crate::oidc::finish_login(&mut session, &provider, &params).await;
+        // NOTE: The session is saved even if the login failed,
+        // since the state of the login was consumed.
+        session.save().await.map_err(IntoResponse::into_response)?;
+        let login = login.map_err(IntoResponse::into_response)?;
+        Ok(Self { session, login })
+    }
+}
@@ -1,12 +1,16 @@
-use crate::{HashedPassword, SessionFields, SessionId, User};
-
-macro_rules! future {
-    (Output = Result<$type:ty, Error>) => {
-        impl Future<Output = Result<$type, Self::Error>> + Send
-    }
-}
+use crate::email::DEFAULT_EMAIL_NORMALIZATION;
+use crate::password::DEFAULT_PASSWORD_POLICY;
+use crate::{
+    AuditEvent, EmailNormalization, HashedPassword, LoginIdentifier,
+    MIN_SESSION_TOKEN_BYTES, PasswordPolicy, SessionFields, SessionId,
+    SessionInfo, SessionUpdate, User,
+};
 
 /// The interface for a backend.
+///
+/// This combines a session store with a user store.
+/// It can be implemented directly, or obtained by combining
+/// a [`SessionStore`] and a [`UserStore`] through [`ComposedBackend`].
 pub trait Backend: Send + Sized {
     /// The user type.
     type User: User;
@@ -19,12 +23,15 @@ pub trait Backend: Send + Sized {
     fn load_session_data(
         &self,
         id: &SessionId,
-    ) -> future!(Output = Result<Option<SessionFields<Self>>, Error>);
+    ) -> future!(Output = Result<Option<SessionFields<<Self::User as User>::Id, Self::SessionData>>, Error>);
 
     /// Create a new instance of session data.
     ///
     /// This is called when a user does not have an existing session,
     /// ie. when they visit the site for the first time.
+    /// This must not store anything,
+    /// since the session is only stored once it is saved,
+    /// see [`Session::is_stored()`](crate::Session::is_stored).
     fn create_session_data(
         &self,
     ) -> future!(Output = Result<Self::SessionData, Error>);
@@ -32,13 +39,73 @@ pub trait Backend: Send + Sized {
     /// Update the session data.
     ///
     /// This is called when the data associated with a session has changed.
+    ///
+    /// The update must only be stored if the version currently stored
+    /// equals `update.version`,
+    /// in which case the stored version becomes `update.version + 1`
+    /// and this returns `true`.
+    /// A version of `0` means the session should be created,
+    /// and must only succeed if no session with this id exists yet.
+    /// If the versions do not match, nothing is stored and this returns `false`.
     fn update_session_data(
+        &self,
+        update: SessionUpdate<'_, <Self::User as User>::Id, Self::SessionData>,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete a session.
+    ///
+    /// This is called when a session is saved after its user changed,
+    /// to delete it under the id of its previous token.
+    /// If no session with this id exists, nothing is deleted.
+    fn delete_session_data(
         &self,
         id: &SessionId,
-        user_id: Option<&<Self::User as User>::Id>,
-        data: &Self::SessionData,
     ) -> future!(Output = Result<(), Error>);
 
+    /// Merge session data after a concurrent update.
+    ///
+    /// This is called when saving a session failed because
+    /// it was modified since it was loaded.
+    /// `ours` is the data of the session being saved,
+    /// and `theirs` is the data currently stored.
+    /// Return the merged data to retry saving it,
+    /// or `None` to fail with [`SaveError::Conflict`](crate::SaveError::Conflict).
+    ///
+    /// By default, this never merges.
+    fn merge_session_data(
+        &self,
+        ours: &Self::SessionData,
+        theirs: Self::SessionData,
+    ) -> Option<Self::SessionData> {
+        let _ = (ours, theirs);
+        None
+    }
+
+    /// Update session data when the user of the session changes.
+    ///
+    /// This is called when a user logs in or out,
+    /// with `previous` the user that was logged in (if any)
+    /// and `user` the user that is now logged in (if any).
+    /// `data` belongs to the previous user, or to an anonymous visitor.
+    /// Modify it to decide what carries over,
+    /// for example to keep the shopping cart of an anonymous visitor,
+    /// or to clear or merge the data when another user logs in.
+    ///
+    /// Only the data of the current session is available;
+    /// the data of other (previous) sessions of `user` is not loaded,
+    /// so data that should follow a user across sessions
+    /// must be stored with the user instead.
+    ///
+    /// By default, the data is kept as is.
+    fn carry_session_data(
+        &self,
+        data: &mut Self::SessionData,
+        previous: Option<&<Self::User as User>::Id>,
+        user: Option<&<Self::User as User>::Id>,
+    ) {
+        let _ = (data, previous, user);
+    }
+
     /// Load a user by their id.
     fn load_user(
         &self,
@@ -46,17 +113,94 @@ pub trait Backend: Send + Sized {
     ) -> future!(Output = Result<Option<Self::User>, Error>);
 
     /// Load a user by their email address.
+    ///
+    /// The email address has been normalized,
+    /// see [`email_normalization()`](Self::email_normalization).
     fn load_user_by_email(
         &self,
         email: &str,
     ) -> future!(Output = Result<Option<Self::User>, Error>);
 
+    /// Load a user by the identifier they log in with.
+    ///
+    /// By default, users can only log in by email address,
+    /// and this returns `None` for other identifiers.
+    fn load_user_by_identifier(
+        &self,
+        identifier: &LoginIdentifier,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = match identifier {
+            LoginIdentifier::Email(email) => {
+                Some(self.load_user_by_email(email))
+            }
+            _ => None,
+        };
+        async move {
+            match future {
+                Some(future) => future.await,
+                None => Ok(None),
+            }
+        }
+    }
+
     /// Update the user password.
     fn update_user_password(
         &self,
         id: &<Self::User as User>::Id,
         hashed_password: &HashedPassword,
     ) -> future!(Output = Result<(), Error>);
+
+    /// Load the previous hashed passwords of a user, most recent first.
+    ///
+    /// This returns at most `limit` hashed passwords,
+    /// see [`PasswordPolicy::history_depth`].
+    ///
+    /// By default, no history is kept and this returns an empty list,
+    /// so only the current password is checked.
+    fn load_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        limit: usize,
+    ) -> future!(Output = Result<Vec<HashedPassword>, Error>) {
+        let _ = (id, limit);
+        async { Ok(Vec::new()) }
+    }
+
+    /// Add a previous hashed password to the history of a user,
+    /// and remove all but the `keep` most recent ones.
+    ///
+    /// By default, no history is kept and this does nothing.
+    fn add_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+        keep: usize,
+    ) -> future!(Output = Result<(), Error>) {
+        let _ = (id, hashed_password, keep);
+        async { Ok(()) }
+    }
+
+    /// Get the policy new passwords must satisfy.
+    fn password_policy(&self) -> &PasswordPolicy {
+        &DEFAULT_PASSWORD_POLICY
+    }
+
+    /// Get how email addresses are normalized
+    /// before they are looked up or stored.
+    fn email_normalization(&self) -> &EmailNormalization {
+        &DEFAULT_EMAIL_NORMALIZATION
+    }
+
+    /// Record a security-relevant event for auditing.
+    ///
+    /// By default, events are discarded.
+    fn record_audit_event(
+        &self,
+        event: &AuditEvent<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let _ = event;
+        async { Ok(()) }
+    }
 }
 
 /// The interface for a backend that stores the session id in a cookie.
@@ -65,4 +209,511 @@ pub trait CookieSessionBackend: Backend {
     fn session_cookie_name(&self) -> &str {
         "sessionid"
     }
+
+    /// Get the number of random bytes in new session tokens.
+    ///
+    /// This must be at least [`MIN_SESSION_TOKEN_BYTES`].
+    fn session_token_bytes(&self) -> usize {
+        MIN_SESSION_TOKEN_BYTES
+    }
+}
+
+/// The interface for a backend that can list and delete
+/// all sessions of a user.
+pub trait UserSessionsBackend: Backend {
+    /// Delete all sessions of a user, except the given session.
+    fn delete_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        except: &SessionId,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// List all sessions of a user.
+    fn list_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<SessionInfo>, Error>);
+
+    /// Delete a session of a user.
+    ///
+    /// If the session does not belong to the user, nothing is deleted.
+    fn delete_user_session(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a backend that can create new users.
+pub trait RegistrationBackend: Backend {
+    /// Create a new user.
+    ///
+    /// The email address has been normalized,
+    /// see [`email_normalization()`](Backend::email_normalization).
+    /// If a user with this email address already exists, this returns `None`.
+    fn create_user(
+        &self,
+        email: &str,
+        hashed_password: Option<&HashedPassword>,
+    ) -> future!(Output = Result<Option<Self::User>, Error>);
+}
+
+/// The interface for storing sessions.
+pub trait SessionStore: Send + Sized {
+    /// The type used to identify the user associated with a session.
+    type UserId: Clone + PartialEq + std::fmt::Debug + Send;
+    /// The implementation-defined session data type.
+    type SessionData: Send;
+    /// The session store error type.
+    type Error: std::error::Error + Send;
+
+    /// Load the session data.
+    fn load_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<Option<SessionFields<Self::UserId, Self::SessionData>>, Error>);
+
+    /// Create a new instance of session data.
+    ///
+    /// See [`Backend::create_session_data()`].
+    fn create_session_data(
+        &self,
+    ) -> future!(Output = Result<Self::SessionData, Error>);
+
+    /// Update the session data.
+    ///
+    /// See [`Backend::update_session_data()`].
+    fn update_session_data(
+        &self,
+        update: SessionUpdate<'_, Self::UserId, Self::SessionData>,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete a session.
+    ///
+    /// See [`Backend::delete_session_data()`].
+    fn delete_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Merge session data after a concurrent update.
+    ///
+    /// See [`Backend::merge_session_data()`].
+    fn merge_session_data(
+        &self,
+        ours: &Self::SessionData,
+        theirs: Self::SessionData,
+    ) -> Option<Self::SessionData> {
+        let _ = (ours, theirs);
+        None
+    }
+
+    /// Update session data when the user of the session changes.
+    ///
+    /// See [`Backend::carry_session_data()`].
+    fn carry_session_data(
+        &self,
+        data: &mut Self::SessionData,
+        previous: Option<&Self::UserId>,
+        user: Option<&Self::UserId>,
+    ) {
+        let _ = (data, previous, user);
+    }
+}
+
+/// The interface for a session store that stores the session id in a cookie.
+pub trait CookieSessionStore: SessionStore {
+    /// Get the name of the session cookie.
+    fn session_cookie_name(&self) -> &str {
+        "sessionid"
+    }
+
+    /// Get the number of random bytes in new session tokens.
+    ///
+    /// This must be at least [`MIN_SESSION_TOKEN_BYTES`].
+    fn session_token_bytes(&self) -> usize {
+        MIN_SESSION_TOKEN_BYTES
+    }
+}
+
+/// The interface for a session store that can list and delete
+/// all sessions of a user.
+///
+/// See [`UserSessionsBackend`].
+pub trait UserSessionsStore: SessionStore {
+    /// Delete all sessions of a user, except the given session.
+    fn delete_user_sessions(
+        &self,
+        user_id: &Self::UserId,
+        except: &SessionId,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// List all sessions of a user.
+    fn list_user_sessions(
+        &self,
+        user_id: &Self::UserId,
+    ) -> future!(Output = Result<Vec<SessionInfo>, Error>);
+
+    /// Delete a session of a user.
+    ///
+    /// See [`UserSessionsBackend::delete_user_session()`].
+    fn delete_user_session(
+        &self,
+        user_id: &Self::UserId,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for storing users.
+pub trait UserStore: Send + Sized {
+    /// The user type.
+    type User: User;
+    /// The user store error type.
+    type Error: std::error::Error + Send;
+
+    /// Load a user by their id.
+    fn load_user(
+        &self,
+        id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Option<Self::User>, Error>);
+
+    /// Load a user by their email address.
+    ///
+    /// See [`Backend::load_user_by_email()`].
+    fn load_user_by_email(
+        &self,
+        email: &str,
+    ) -> future!(Output = Result<Option<Self::User>, Error>);
+
+    /// Load a user by the identifier they log in with.
+    ///
+    /// See [`Backend::load_user_by_identifier()`].
+    fn load_user_by_identifier(
+        &self,
+        identifier: &LoginIdentifier,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = match identifier {
+            LoginIdentifier::Email(email) => {
+                Some(self.load_user_by_email(email))
+            }
+            _ => None,
+        };
+        async move {
+            match future {
+                Some(future) => future.await,
+                None => Ok(None),
+            }
+        }
+    }
+
+    /// Update the user password.
+    fn update_user_password(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load the previous hashed passwords of a user, most recent first.
+    ///
+    /// See [`Backend::load_password_history()`].
+    fn load_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        limit: usize,
+    ) -> future!(Output = Result<Vec<HashedPassword>, Error>) {
+        let _ = (id, limit);
+        async { Ok(Vec::new()) }
+    }
+
+    /// Add a previous hashed password to the history of a user.
+    ///
+    /// See [`Backend::add_password_history()`].
+    fn add_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+        keep: usize,
+    ) -> future!(Output = Result<(), Error>) {
+        let _ = (id, hashed_password, keep);
+        async { Ok(()) }
+    }
+
+    /// Get the policy new passwords must satisfy.
+    fn password_policy(&self) -> &PasswordPolicy {
+        &DEFAULT_PASSWORD_POLICY
+    }
+
+    /// Get how email addresses are normalized
+    /// before they are looked up or stored.
+    fn email_normalization(&self) -> &EmailNormalization {
+        &DEFAULT_EMAIL_NORMALIZATION
+    }
+
+    /// Record a security-relevant event for auditing.
+    ///
+    /// See [`Backend::record_audit_event()`].
+    fn record_audit_event(
+        &self,
+        event: &AuditEvent<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let _ = event;
+        async { Ok(()) }
+    }
+}
+
+/// The interface for a user store that can create new users.
+///
+/// See [`RegistrationBackend`].
+pub trait RegistrationStore: UserStore {
+    /// Create a new user.
+    ///
+    /// See [`RegistrationBackend::create_user()`].
+    fn create_user(
+        &self,
+        email: &str,
+        hashed_password: Option<&HashedPassword>,
+    ) -> future!(Output = Result<Option<Self::User>, Error>);
+}
+
+/// A backend composed of a separate session store and user store.
+#[derive(Clone, Debug)]
+pub struct ComposedBackend<S, U> {
+    /// The session store.
+    pub sessions: S,
+    /// The user store.
+    pub users: U,
+}
+
+impl<S, U> ComposedBackend<S, U> {
+    /// Combine a session store with a user store.
+    pub fn new(sessions: S, users: U) -> Self {
+        Self { sessions, users }
+    }
+}
+
+/// The error type of a [`ComposedBackend`].
+#[derive(Debug)]
+pub enum ComposedError<S, U> {
+    /// An error from the session store.
+    Session(S),
+    /// An error from the user store.
+    User(U),
+}
+
+impl<S: std::fmt::Display, U: std::fmt::Display> std::fmt::Display
+    for ComposedError<S, U>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Session(e) => write!(f, "session store error: {e}"),
+            Self::User(e) => write!(f, "user store error: {e}"),
+        }
+    }
+}
+
+impl<S, U> std::error::Error for ComposedError<S, U>
+where
+    S: std::error::Error + 'static,
+    U: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Session(e) => Some(e),
+            Self::User(e) => Some(e),
+        }
+    }
+}
+
+impl<S, U> Backend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: UserStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    type User = U::User;
+    type SessionData = S::SessionData;
+    type Error = ComposedError<S::Error, U::Error>;
+
+    fn load_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<Option<SessionFields<<Self::User as User>::Id, Self::SessionData>>, Error>)
+    {
+        let future = self.sessions.load_session_data(id);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn create_session_data(
+        &self,
+    ) -> future!(Output = Result<Self::SessionData, Error>) {
+        let future = self.sessions.create_session_data();
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn update_session_data(
+        &self,
+        update: SessionUpdate<'_, <Self::User as User>::Id, Self::SessionData>,
+    ) -> future!(Output = Result<bool, Error>) {
+        let future = self.sessions.update_session_data(update);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn delete_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.sessions.delete_session_data(id);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn merge_session_data(
+        &self,
+        ours: &Self::SessionData,
+        theirs: Self::SessionData,
+    ) -> Option<Self::SessionData> {
+        self.sessions.merge_session_data(ours, theirs)
+    }
+
+    fn carry_session_data(
+        &self,
+        data: &mut Self::SessionData,
+        previous: Option<&<Self::User as User>::Id>,
+        user: Option<&<Self::User as User>::Id>,
+    ) {
+        self.sessions.carry_session_data(data, previous, user)
+    }
+
+    fn load_user(
+        &self,
+        id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = self.users.load_user(id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_user_by_email(
+        &self,
+        email: &str,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = self.users.load_user_by_email(email);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_user_by_identifier(
+        &self,
+        identifier: &LoginIdentifier,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = self.users.load_user_by_identifier(identifier);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn update_user_password(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.update_user_password(id, hashed_password);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        limit: usize,
+    ) -> future!(Output = Result<Vec<HashedPassword>, Error>) {
+        let future = self.users.load_password_history(id, limit);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn add_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+        keep: usize,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.add_password_history(id, hashed_password, keep);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn password_policy(&self) -> &PasswordPolicy {
+        self.users.password_policy()
+    }
+
+    fn email_normalization(&self) -> &EmailNormalization {
+        self.users.email_normalization()
+    }
+
+    fn record_audit_event(
+        &self,
+        event: &AuditEvent<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.record_audit_event(event);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+impl<S, U> CookieSessionBackend for ComposedBackend<S, U>
+where
+    Self: Backend,
+    S: CookieSessionStore,
+{
+    fn session_cookie_name(&self) -> &str {
+        self.sessions.session_cookie_name()
+    }
+
+    fn session_token_bytes(&self) -> usize {
+        self.sessions.session_token_bytes()
+    }
+}
+
+impl<S, U> UserSessionsBackend for ComposedBackend<S, U>
+where
+    S: UserSessionsStore<UserId = <U::User as User>::Id>,
+    U: UserStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn delete_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        except: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.sessions.delete_user_sessions(user_id, except);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn list_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<SessionInfo>, Error>) {
+        let future = self.sessions.list_user_sessions(user_id);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+
+    fn delete_user_session(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.sessions.delete_user_session(user_id, id);
+        async move { future.await.map_err(ComposedError::Session) }
+    }
+}
+
+impl<S, U> RegistrationBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: RegistrationStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn create_user(
+        &self,
+        email: &str,
+        hashed_password: Option<&HashedPassword>,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let future = self.users.create_user(email, hashed_password);
+        async move { future.await.map_err(ComposedError::User) }
+    }
 }
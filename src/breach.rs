@@ -0,0 +1,314 @@
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The number of hex characters of a hash prefix in a range query.
+const PREFIX_LENGTH: usize = 5;
+
+/// A source of compromised password hashes.
+///
+/// Sources are queried by the first 5 hex characters of the SHA-1 hash
+/// of a password (k-anonymity), so they can be implemented by
+/// a remote service such as the Pwned Passwords API
+/// without revealing the password or its full hash.
+pub trait BreachedPasswordSource: Send + Sync {
+    /// The error type of the source.
+    type Error: std::error::Error + Send;
+
+    /// Load all compromised hashes starting with `prefix`.
+    ///
+    /// `prefix` consists of 5 uppercase hex characters.
+    /// This returns the remaining 35 hex characters of each hash,
+    /// together with how often the password was seen in breaches.
+    fn load_range(
+        &self,
+        prefix: &str,
+    ) -> impl Future<Output = Result<Vec<(String, u64)>, Self::Error>> + Send;
+}
+
+/// Check whether a password appears in a source of compromised passwords.
+pub(crate) async fn is_breached<S: BreachedPasswordSource>(
+    source: &S,
+    password: &str,
+) -> Result<bool, S::Error> {
+    let hash = sha1_smol::Sha1::from(password)
+        .digest()
+        .to_string()
+        .to_uppercase();
+    let (prefix, suffix) = hash.split_at(PREFIX_LENGTH);
+    let range = source.load_range(prefix).await?;
+    Ok(range
+        .iter()
+        .any(|(s, count)| *count > 0 && s.eq_ignore_ascii_case(suffix)))
+}
+
+/// A [`BreachedPasswordSource`] with its error type erased,
+/// so it can be stored in a [`BreachCheck`].
+trait DynBreachedPasswordSource: Send + Sync {
+    /// Check whether a password appears in the source.
+    ///
+    /// If the source fails, this returns `false`.
+    fn is_breached<'a>(
+        &'a self,
+        password: &'a str,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+}
+
+impl<S: BreachedPasswordSource> DynBreachedPasswordSource for S {
+    fn is_breached<'a>(
+        &'a self,
+        password: &'a str,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(
+            async move { is_breached(self, password).await.unwrap_or(false) },
+        )
+    }
+}
+
+/// A check of new passwords against a source of compromised passwords,
+/// see [`PasswordPolicy::breach_check`](crate::PasswordPolicy::breach_check).
+///
+/// Clones of this check share the same source.
+#[derive(Clone)]
+pub struct BreachCheck(Arc<dyn DynBreachedPasswordSource>);
+
+impl BreachCheck {
+    /// Check passwords against `source`.
+    pub fn new<S: BreachedPasswordSource + 'static>(source: S) -> Self {
+        Self(Arc::new(source))
+    }
+
+    /// Check whether a password appears in the source.
+    ///
+    /// If the source fails, this returns `false`.
+    pub(crate) async fn is_breached(&self, password: &str) -> bool {
+        self.0.is_breached(password).await
+    }
+}
+
+impl std::fmt::Debug for BreachCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BreachCheck([...])")
+    }
+}
+
+/// A local file of compromised password hashes.
+///
+/// The file must contain one `HASH:COUNT` line per password,
+/// where `HASH` is the SHA-1 hash of the password in hex,
+/// sorted by hash, such as the files produced by
+/// the Pwned Passwords downloader.
+///
+/// Ranges are found by binary search,
+/// so the file is never loaded into memory as a whole.
+/// The file is read on a blocking thread of the Tokio runtime.
+#[derive(Clone, Debug)]
+pub struct BreachedPasswordFile {
+    path: PathBuf,
+}
+
+impl BreachedPasswordFile {
+    /// Use the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn load_range_sync(
+        &self,
+        prefix: &str,
+    ) -> std::io::Result<Vec<(String, u64)>> {
+        let mut file = BufReader::new(std::fs::File::open(&self.path)?);
+        let length = file.get_ref().metadata()?.len();
+        // Find the first line starting at or after an offset
+        // with a hash prefix not less than `prefix`.
+        let (mut low, mut high) = (0, length);
+        let mut line = String::new();
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let found = read_line_from(&mut file, middle, &mut line)?;
+            if !found || line_prefix(&line).as_str() >= prefix {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        let mut range = Vec::new();
+        let mut found = read_line_from(&mut file, low, &mut line)?;
+        while found && line_prefix(&line) == prefix {
+            if let Some((hash, count)) = line.trim_end().split_once(':') {
+                let count = count.parse().unwrap_or(1);
+                range.push((hash[PREFIX_LENGTH..].to_owned(), count));
+            }
+            line.clear();
+            found = file.read_line(&mut line)? > 0;
+        }
+        Ok(range)
+    }
+}
+
+/// Read the first complete line starting at or after `offset`.
+///
+/// This returns `false` if there is no such line.
+fn read_line_from(
+    file: &mut BufReader<std::fs::File>,
+    offset: u64,
+    line: &mut String,
+) -> std::io::Result<bool> {
+    line.clear();
+    if offset == 0 {
+        file.seek(SeekFrom::Start(0))?;
+    } else {
+        // Skip the rest of the line containing the byte before `offset`,
+        // which ends exactly before `offset` if a line starts there.
+        file.seek(SeekFrom::Start(offset - 1))?;
+        file.read_line(line)?;
+        line.clear();
+    }
+    Ok(file.read_line(line)? > 0)
+}
+
+/// Get the uppercase hash prefix of a line.
+fn line_prefix(line: &str) -> String {
+    line.get(..PREFIX_LENGTH).unwrap_or(line).to_uppercase()
+}
+
+impl BreachedPasswordSource for BreachedPasswordFile {
+    type Error = std::io::Error;
+
+    // NOTE: The file is read on a blocking thread,
+    // so that slow disks do not stall the executor.
+    async fn load_range(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, u64)>, Self::Error> {
+        let file = self.clone();
+        let prefix = prefix.to_owned();
+        tokio::task::spawn_blocking(move || file.load_range_sync(&prefix))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file of compromised password hashes, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, lines: &[&str]) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("autho-{}-{name}.txt", std::process::id()));
+            let mut contents = lines.join("\r\n");
+            contents.push_str("\r\n");
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+
+        fn source(&self) -> BreachedPasswordFile {
+            BreachedPasswordFile::new(&self.0)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const LINES: &[&str] = &[
+        "0000000000000000000000000000000000000000:1",
+        "00000FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:2",
+        "1234500000000000000000000000000000000000:3",
+        "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:10",
+        "5BAA6FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:4",
+        "5BAA700000000000000000000000000000000000:5",
+        "FFFFF00000000000000000000000000000000000:6",
+        "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF:7",
+    ];
+
+    #[test]
+    fn load_range() {
+        let file = TempFile::new("load-range", LINES);
+        let source = file.source();
+        let range = |prefix| source.load_range_sync(prefix).unwrap();
+        let suffix = |c: &str| c.repeat(35);
+        assert_eq!(range("00000"), vec![(suffix("0"), 1), (suffix("F"), 2)]);
+        assert_eq!(range("12345"), vec![(suffix("0"), 3)]);
+        assert_eq!(
+            range("5BAA6"),
+            vec![
+                ("1E4C9B93F3F0682250B6CF8331B7EE68FD8".to_owned(), 10),
+                (suffix("F"), 4),
+            ],
+        );
+        assert_eq!(range("5BAA7"), vec![(suffix("0"), 5)]);
+        assert_eq!(range("FFFFF"), vec![(suffix("0"), 6), (suffix("F"), 7)]);
+    }
+
+    #[test]
+    fn load_missing_range() {
+        let file = TempFile::new("missing-range", LINES);
+        let source = file.source();
+        for prefix in ["00001", "12344", "12346", "5BAA5", "ABCDE", "FFFFE"] {
+            assert!(source.load_range_sync(prefix).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn load_range_single_line() {
+        let file = TempFile::new("single-line", &LINES[3..4]);
+        let source = file.source();
+        assert_eq!(source.load_range_sync("5BAA6").unwrap().len(), 1);
+        assert!(source.load_range_sync("00000").unwrap().is_empty());
+        assert!(source.load_range_sync("FFFFF").unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_range_empty_file() {
+        let file = TempFile::new("empty", &[]);
+        assert!(file.source().load_range_sync("5BAA6").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn check_password() {
+        let file = TempFile::new("check", LINES);
+        let source = file.source();
+        assert!(is_breached(&source, "password").await.unwrap());
+        assert!(!is_breached(&source, "correct horse").await.unwrap());
+
+        let check = BreachCheck::new(file.source());
+        assert!(check.is_breached("password").await);
+        assert!(!check.is_breached("correct horse").await);
+    }
+
+    #[tokio::test]
+    async fn validate_password() {
+        let file = TempFile::new("validate", LINES);
+        let policy = crate::PasswordPolicy {
+            min_length: 8,
+            #[cfg(feature = "password-strength")]
+            min_score: 0,
+            breach_check: Some(BreachCheck::new(file.source())),
+            ..crate::PasswordPolicy::new()
+        };
+        let valid = |password: &str| {
+            crate::ValidPassword::new(password.to_owned(), &policy, &[])
+        };
+        assert!(matches!(
+            valid("password").await,
+            Err(crate::BadPassword::Breached),
+        ));
+        assert!(valid("correct horse").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn check_password_source_failure() {
+        let check = BreachCheck::new(BreachedPasswordFile::new(
+            std::env::temp_dir().join("autho-missing.txt"),
+        ));
+        assert!(!check.is_breached("password").await);
+    }
+}
@@ -0,0 +1,1009 @@
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use lru::LruCache;
+
+#[cfg(feature = "jwt")]
+use crate::jwt::{RefreshTokenHash, RefreshTokenRecord};
+#[cfg(feature = "oidc")]
+use crate::oidc::OidcIdentity;
+#[cfg(feature = "passkey")]
+use crate::passkey::PasskeyCredential;
+use crate::{
+    ApiTokenBackend, ApiTokenHash, ApiTokenRecord, AuditEvent, Backend,
+    CookieSessionBackend, EmailChangeBackend, EmailChangeRecord,
+    EmailChangeTokenHash, EmailNormalization, HashedPassword, LoginIdentifier,
+    LoginTokenHash, LoginTokenRecord, MagicLinkBackend, PasswordPolicy,
+    RegistrationBackend, RememberMeBackend, RememberMeHash, RememberMeRecord,
+    SessionFields, SessionId, SessionInfo, SessionUpdate, User,
+    UserSessionsBackend,
+};
+
+/// Statistics about the use of a cache.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct CacheStats {
+    /// The number of lookups that were answered by the cache.
+    pub hits: u64,
+    /// The number of lookups that had to be forwarded to the backend.
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// The fraction of lookups answered by the cache, in `[0, 1]`.
+    ///
+    /// This returns `0` if there have been no lookups yet.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// A bounded cache where entries expire after a fixed duration.
+///
+/// Every invalidation increments an epoch and leaves a tombstone,
+/// so that a value loaded while its key was invalidated
+/// is not inserted afterwards, see [`Cache::insert()`].
+struct Cache<K: Hash + Eq, V> {
+    entries: Mutex<Entries<K, V>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// The entries of a cache, with the number of invalidations so far.
+struct Entries<K: Hash + Eq, V> {
+    lru: LruCache<K, (Instant, V)>,
+    /// The epoch at which each recently invalidated key was invalidated.
+    tombstones: LruCache<K, u64>,
+    /// The epoch at which any key without a tombstone
+    /// was invalidated at the latest.
+    ///
+    /// This is raised when tombstones are evicted,
+    /// and when entries are invalidated by [`Cache::remove_where()`].
+    floor: u64,
+    epoch: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Cache<K, V> {
+    fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(Entries {
+                lru: LruCache::new(capacity),
+                tombstones: LruCache::new(capacity),
+                floor: 0,
+                epoch: 0,
+            }),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a value, or the current epoch to pass to `insert()`
+    /// once the value has been loaded.
+    fn get(&self, key: &K) -> Result<V, u64> {
+        let mut entries = self.entries.lock().unwrap();
+        let value = match entries.lru.get(key) {
+            Some((inserted, value)) if inserted.elapsed() < self.ttl => {
+                Ok(value.clone())
+            }
+            Some(_) => {
+                entries.lru.pop(key);
+                Err(entries.epoch)
+            }
+            None => Err(entries.epoch),
+        };
+        if value.is_ok() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Insert a value loaded when the cache was at `epoch`.
+    ///
+    /// If the key was invalidated since,
+    /// the value may be stale and is not inserted.
+    fn insert(&self, key: K, value: V, epoch: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        let invalidated = match entries.tombstones.peek(&key) {
+            Some(&invalidated) => invalidated.max(entries.floor),
+            None => entries.floor,
+        };
+        if epoch >= invalidated {
+            entries.lru.put(key, (Instant::now(), value));
+        }
+    }
+
+    fn remove(&self, key: &K) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.epoch += 1;
+        entries.lru.pop(key);
+        let epoch = entries.epoch;
+        if let Some((evicted, invalidated)) =
+            entries.tombstones.push(key.clone(), epoch)
+            && evicted != *key
+        {
+            entries.floor = entries.floor.max(invalidated);
+        }
+    }
+
+    /// Remove all entries matching a predicate.
+    ///
+    /// Values currently being loaded cannot be matched,
+    /// so none of them are inserted afterwards.
+    fn remove_where(&self, mut predicate: impl FnMut(&K, &V) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.epoch += 1;
+        entries.floor = entries.epoch;
+        entries.tombstones.clear();
+        let keys: Vec<K> = entries
+            .lru
+            .iter()
+            .filter(|(key, (_, value))| predicate(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in keys {
+            entries.lru.pop(&key);
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+type SessionCache<B> = Cache<
+    SessionId,
+    SessionFields<
+        <<B as Backend>::User as User>::Id,
+        <B as Backend>::SessionData,
+    >,
+>;
+
+type UserCache<B> =
+    Cache<<<B as Backend>::User as User>::Id, <B as Backend>::User>;
+
+/// A backend that caches sessions and users loaded from another backend.
+///
+/// Entries are evicted when they have not been used recently,
+/// or when they are older than the configured time-to-live.
+/// Entries are invalidated when they are updated through this backend.
+/// All other backend traits are forwarded to the wrapped backend
+/// without caching.
+/// Changes made to the wrapped backend by other means
+/// (such as by another server) are visible after at most the time-to-live.
+///
+/// Clones of this backend share the same cache.
+pub struct CachedBackend<B>
+where
+    B: Backend,
+    <B::User as User>::Id: Hash + Eq,
+{
+    backend: B,
+    sessions: Arc<SessionCache<B>>,
+    users: Arc<UserCache<B>>,
+}
+
+impl<B> CachedBackend<B>
+where
+    B: Backend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    /// Wrap a backend with a cache.
+    ///
+    /// Both the session and the user cache hold at most `capacity` entries,
+    /// and entries are kept for at most `ttl`.
+    pub fn new(backend: B, capacity: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            backend,
+            sessions: Arc::new(Cache::new(capacity, ttl)),
+            users: Arc::new(Cache::new(capacity, ttl)),
+        }
+    }
+
+    /// Get the wrapped backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Get the statistics of the session cache.
+    pub fn session_stats(&self) -> CacheStats {
+        self.sessions.stats()
+    }
+
+    /// Get the statistics of the user cache.
+    pub fn user_stats(&self) -> CacheStats {
+        self.users.stats()
+    }
+}
+
+impl<B> Clone for CachedBackend<B>
+where
+    B: Backend + Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            sessions: self.sessions.clone(),
+            users: self.users.clone(),
+        }
+    }
+}
+
+impl<B> Backend for CachedBackend<B>
+where
+    B: Backend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    type User = B::User;
+    type SessionData = B::SessionData;
+    type Error = B::Error;
+
+    fn load_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<Option<SessionFields<<Self::User as User>::Id, Self::SessionData>>, Error>)
+    {
+        let cached = self.sessions.get(id);
+        let future = self.backend.load_session_data(id);
+        let cache = self.sessions.clone();
+        let id = *id;
+        async move {
+            let epoch = match cached {
+                Ok(fields) => return Ok(Some(fields)),
+                Err(epoch) => epoch,
+            };
+            let fields = future.await?;
+            if let Some(fields) = &fields {
+                cache.insert(id, fields.clone(), epoch);
+            }
+            Ok(fields)
+        }
+    }
+
+    fn create_session_data(
+        &self,
+    ) -> future!(Output = Result<Self::SessionData, Error>) {
+        self.backend.create_session_data()
+    }
+
+    fn update_session_data(
+        &self,
+        update: SessionUpdate<'_, <Self::User as User>::Id, Self::SessionData>,
+    ) -> future!(Output = Result<bool, Error>) {
+        let id = *update.id;
+        let future = self.backend.update_session_data(update);
+        let cache = self.sessions.clone();
+        async move {
+            let result = future.await;
+            cache.remove(&id);
+            result
+        }
+    }
+
+    fn delete_session_data(
+        &self,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.backend.delete_session_data(id);
+        let cache = self.sessions.clone();
+        let id = *id;
+        async move {
+            let result = future.await;
+            cache.remove(&id);
+            result
+        }
+    }
+
+    fn merge_session_data(
+        &self,
+        ours: &Self::SessionData,
+        theirs: Self::SessionData,
+    ) -> Option<Self::SessionData> {
+        self.backend.merge_session_data(ours, theirs)
+    }
+
+    fn carry_session_data(
+        &self,
+        data: &mut Self::SessionData,
+        previous: Option<&<Self::User as User>::Id>,
+        user: Option<&<Self::User as User>::Id>,
+    ) {
+        self.backend.carry_session_data(data, previous, user)
+    }
+
+    fn load_user(
+        &self,
+        id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        let cached = self.users.get(id);
+        let future = self.backend.load_user(id);
+        let cache = self.users.clone();
+        let id = id.clone();
+        async move {
+            let epoch = match cached {
+                Ok(user) => return Ok(Some(user)),
+                Err(epoch) => epoch,
+            };
+            let user = future.await?;
+            if let Some(user) = &user {
+                cache.insert(id, user.clone(), epoch);
+            }
+            Ok(user)
+        }
+    }
+
+    fn load_user_by_email(
+        &self,
+        email: &str,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        self.backend.load_user_by_email(email)
+    }
+
+    fn load_user_by_identifier(
+        &self,
+        identifier: &LoginIdentifier,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        self.backend.load_user_by_identifier(identifier)
+    }
+
+    fn update_user_password(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.backend.update_user_password(id, hashed_password);
+        let cache = self.users.clone();
+        let id = id.clone();
+        async move {
+            let result = future.await;
+            cache.remove(&id);
+            result
+        }
+    }
+
+    fn load_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        limit: usize,
+    ) -> future!(Output = Result<Vec<HashedPassword>, Error>) {
+        self.backend.load_password_history(id, limit)
+    }
+
+    fn add_password_history(
+        &self,
+        id: &<Self::User as User>::Id,
+        hashed_password: &HashedPassword,
+        keep: usize,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.add_password_history(id, hashed_password, keep)
+    }
+
+    fn password_policy(&self) -> &PasswordPolicy {
+        self.backend.password_policy()
+    }
+
+    fn email_normalization(&self) -> &EmailNormalization {
+        self.backend.email_normalization()
+    }
+
+    fn record_audit_event(
+        &self,
+        event: &AuditEvent<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.record_audit_event(event)
+    }
+}
+
+impl<B> UserSessionsBackend for CachedBackend<B>
+where
+    B: UserSessionsBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn delete_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        except: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.backend.delete_user_sessions(user_id, except);
+        let cache = self.sessions.clone();
+        let user_id = user_id.clone();
+        let except = *except;
+        async move {
+            let result = future.await;
+            cache.remove_where(|id, fields| {
+                *id != except && fields.user_id.as_ref() == Some(&user_id)
+            });
+            result
+        }
+    }
+
+    fn list_user_sessions(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<SessionInfo>, Error>) {
+        self.backend.list_user_sessions(user_id)
+    }
+
+    fn delete_user_session(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        id: &SessionId,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.backend.delete_user_session(user_id, id);
+        let cache = self.sessions.clone();
+        let id = *id;
+        async move {
+            let result = future.await;
+            cache.remove(&id);
+            result
+        }
+    }
+}
+
+impl<B> RegistrationBackend for CachedBackend<B>
+where
+    B: RegistrationBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn create_user(
+        &self,
+        email: &str,
+        hashed_password: Option<&HashedPassword>,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        self.backend.create_user(email, hashed_password)
+    }
+}
+
+impl<B> CookieSessionBackend for CachedBackend<B>
+where
+    B: CookieSessionBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn session_cookie_name(&self) -> &str {
+        self.backend.session_cookie_name()
+    }
+
+    fn session_token_bytes(&self) -> usize {
+        self.backend.session_token_bytes()
+    }
+}
+
+impl<B> RememberMeBackend for CachedBackend<B>
+where
+    B: RememberMeBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn remember_me_ttl(&self) -> Duration {
+        self.backend.remember_me_ttl()
+    }
+
+    fn remember_me_grace_period(&self) -> Duration {
+        self.backend.remember_me_grace_period()
+    }
+
+    fn remember_me_cookie_name(&self) -> &str {
+        self.backend.remember_me_cookie_name()
+    }
+
+    fn store_remember_me_token(
+        &self,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_remember_me_token(token)
+    }
+
+    fn load_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<Option<RememberMeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.load_remember_me_token(selector)
+    }
+
+    fn replace_remember_me_token(
+        &self,
+        hash: &RememberMeHash,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<bool, Error>) {
+        self.backend.replace_remember_me_token(hash, token)
+    }
+
+    fn delete_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.delete_remember_me_token(selector)
+    }
+
+    fn delete_user_remember_me_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.delete_user_remember_me_tokens(user_id)
+    }
+}
+
+impl<B> MagicLinkBackend for CachedBackend<B>
+where
+    B: MagicLinkBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn login_token_ttl(&self) -> Duration {
+        self.backend.login_token_ttl()
+    }
+
+    fn store_login_token(
+        &self,
+        token: &LoginTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_login_token(token)
+    }
+
+    fn take_login_token(
+        &self,
+        hash: &LoginTokenHash,
+    ) -> future!(Output = Result<Option<LoginTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.take_login_token(hash)
+    }
+
+    fn delete_user_login_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.delete_user_login_tokens(user_id)
+    }
+}
+
+impl<B> EmailChangeBackend for CachedBackend<B>
+where
+    B: EmailChangeBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn email_change_ttl(&self) -> Duration {
+        self.backend.email_change_ttl()
+    }
+
+    fn store_email_change(
+        &self,
+        change: &EmailChangeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_email_change(change)
+    }
+
+    fn take_email_change(
+        &self,
+        hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.take_email_change(hash)
+    }
+
+    fn take_email_change_by_cancel_hash(
+        &self,
+        cancel_hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.take_email_change_by_cancel_hash(cancel_hash)
+    }
+
+    fn update_user_email(
+        &self,
+        id: &<Self::User as User>::Id,
+        email: &str,
+    ) -> future!(Output = Result<bool, Error>) {
+        let future = self.backend.update_user_email(id, email);
+        let cache = self.users.clone();
+        let id = id.clone();
+        async move {
+            let result = future.await;
+            cache.remove(&id);
+            result
+        }
+    }
+
+    fn delete_user_email_changes(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.delete_user_email_changes(user_id)
+    }
+}
+
+impl<B> ApiTokenBackend for CachedBackend<B>
+where
+    B: ApiTokenBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn api_token_prefix(&self) -> &str {
+        self.backend.api_token_prefix()
+    }
+
+    fn store_api_token(
+        &self,
+        token: &ApiTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_api_token(token)
+    }
+
+    fn load_api_token(
+        &self,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<Option<ApiTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.load_api_token(hash)
+    }
+
+    fn list_api_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<ApiTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.list_api_tokens(user_id)
+    }
+
+    fn update_api_token_last_used(
+        &self,
+        hash: &ApiTokenHash,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.update_api_token_last_used(hash, last_used_at)
+    }
+
+    fn revoke_api_token(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        hash: &ApiTokenHash,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.revoke_api_token(user_id, hash)
+    }
+}
+
+#[cfg(feature = "passkey")]
+impl<B> crate::passkey::PasskeyBackend for CachedBackend<B>
+where
+    B: crate::passkey::PasskeyBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn store_passkey(
+        &self,
+        credential: &PasskeyCredential<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_passkey(credential)
+    }
+
+    fn load_passkey(
+        &self,
+        credential_id: &[u8],
+    ) -> future!(Output = Result<Option<PasskeyCredential<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.load_passkey(credential_id)
+    }
+
+    fn list_passkeys(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<PasskeyCredential<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.list_passkeys(user_id)
+    }
+
+    fn update_passkey_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.update_passkey_sign_count(
+            credential_id,
+            sign_count,
+            last_used_at,
+        )
+    }
+}
+
+#[cfg(feature = "oidc")]
+impl<B> crate::oidc::OidcBackend for CachedBackend<B>
+where
+    B: crate::oidc::OidcBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn load_user_by_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> future!(Output = Result<Option<Self::User>, Error>) {
+        self.backend.load_user_by_identity(issuer, subject)
+    }
+
+    fn link_identity(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        identity: &OidcIdentity,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.link_identity(user_id, identity)
+    }
+
+    fn unlink_identity(
+        &self,
+        user_id: &<Self::User as User>::Id,
+        issuer: &str,
+        subject: &str,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.unlink_identity(user_id, issuer, subject)
+    }
+
+    fn list_identities(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<OidcIdentity>, Error>) {
+        self.backend.list_identities(user_id)
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl<B> crate::jwt::RefreshTokenBackend for CachedBackend<B>
+where
+    B: crate::jwt::RefreshTokenBackend,
+    B::User: Clone,
+    B::SessionData: Clone,
+    <B::User as User>::Id: Hash + Eq,
+{
+    fn store_refresh_token(
+        &self,
+        token: &RefreshTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.store_refresh_token(token)
+    }
+
+    fn load_refresh_token(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<Option<RefreshTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        self.backend.load_refresh_token(hash)
+    }
+
+    fn mark_refresh_token_used(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<bool, Error>) {
+        self.backend.mark_refresh_token_used(hash)
+    }
+
+    fn revoke_refresh_token_family(
+        &self,
+        family: &uuid::Uuid,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.revoke_refresh_token_family(family)
+    }
+
+    fn revoke_user_refresh_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        self.backend.revoke_user_refresh_tokens(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBackend;
+    use crate::{SessionMetadata, SessionToken, ValidPassword};
+
+    fn cache() -> Cache<u32, &'static str> {
+        Cache::new(NonZeroUsize::new(8).unwrap(), Duration::from_secs(60))
+    }
+
+    #[test]
+    fn insert_and_get() {
+        let cache = cache();
+        let epoch = cache.get(&1).unwrap_err();
+        cache.insert(1, "one", epoch);
+        assert_eq!(cache.get(&1), Ok("one"));
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn stale_insert_after_remove() {
+        let cache = cache();
+        let epoch = cache.get(&1).unwrap_err();
+        // The entry is invalidated while it is being loaded.
+        cache.remove(&1);
+        cache.insert(1, "stale", epoch);
+        assert!(cache.get(&1).is_err());
+    }
+
+    #[test]
+    fn stale_insert_after_remove_where() {
+        let cache = cache();
+        let epoch = cache.get(&1).unwrap_err();
+        cache.remove_where(|_, _| false);
+        cache.insert(1, "stale", epoch);
+        assert!(cache.get(&1).is_err());
+    }
+
+    #[test]
+    fn expired_entry() {
+        let cache = Cache::new(NonZeroUsize::new(8).unwrap(), Duration::ZERO);
+        let epoch = cache.get(&1).unwrap_err();
+        cache.insert(1, "one", epoch);
+        assert!(cache.get(&1).is_err());
+    }
+
+    #[test]
+    fn insert_after_remove_of_other_key() {
+        let cache = cache();
+        let epoch = cache.get(&1).unwrap_err();
+        cache.remove(&2);
+        cache.insert(1, "one", epoch);
+        assert_eq!(cache.get(&1), Ok("one"));
+    }
+
+    #[test]
+    fn stale_insert_after_tombstone_eviction() {
+        let cache = cache();
+        let epoch = cache.get(&1).unwrap_err();
+        cache.remove(&1);
+        // Evict the tombstone of the first key.
+        for key in 2..10 {
+            cache.remove(&key);
+        }
+        cache.insert(1, "stale", epoch);
+        assert!(cache.get(&1).is_err());
+    }
+
+    fn cached(backend: &TestBackend) -> CachedBackend<TestBackend> {
+        let capacity = NonZeroUsize::new(8).unwrap();
+        CachedBackend::new(backend.clone(), capacity, Duration::from_secs(60))
+    }
+
+    fn insert_session(backend: &TestBackend, user_id: u64) -> SessionId {
+        let id = SessionToken::generate(32).id();
+        let fields = SessionFields {
+            user_id: Some(user_id),
+            data: Default::default(),
+            version: 0,
+            authenticated_at: None,
+            impersonator: None,
+            metadata: SessionMetadata::new(),
+        };
+        backend.state().sessions.insert(id, fields);
+        id
+    }
+
+    #[tokio::test]
+    async fn load_user_cached() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let cached = cached(&backend);
+        let user = cached.load_user(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.email, "a@example.com");
+
+        // Changes made to the wrapped backend are not seen.
+        backend.state().users[0].email = "b@example.com".to_owned();
+        let user = cached.load_user(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.email, "a@example.com");
+        let stats = cached.user_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+
+        // Unknown users are not cached.
+        assert!(cached.load_user(&2).await.unwrap().is_none());
+        backend.add_user("c@example.com");
+        assert!(cached.load_user(&2).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn update_user_invalidates() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let cached = cached(&backend);
+        cached.load_user(&user_id).await.unwrap();
+
+        let policy = PasswordPolicy::default();
+        let password = "correct horse battery".to_owned();
+        let password =
+            ValidPassword::new(password, &policy, &[]).await.unwrap();
+        let hashed_password = HashedPassword::new(&password);
+        cached
+            .update_user_password(&user_id, &hashed_password)
+            .await
+            .unwrap();
+        let user = cached.load_user(&user_id).await.unwrap().unwrap();
+        assert!(user.hashed_password.is_some());
+
+        assert!(
+            cached
+                .update_user_email(&user_id, "b@example.com")
+                .await
+                .unwrap()
+        );
+        let user = cached.load_user(&user_id).await.unwrap().unwrap();
+        assert_eq!(user.email, "b@example.com");
+        let stats = cached.user_stats();
+        assert_eq!((stats.hits, stats.misses), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn delete_session_invalidates() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let id = insert_session(&backend, user_id);
+        let cached = cached(&backend);
+        assert!(cached.load_session_data(&id).await.unwrap().is_some());
+        assert!(cached.load_session_data(&id).await.unwrap().is_some());
+
+        cached.delete_session_data(&id).await.unwrap();
+        assert!(cached.load_session_data(&id).await.unwrap().is_none());
+        let stats = cached.session_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn delete_user_sessions_invalidates() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let other_user_id = backend.add_user("b@example.com");
+        let current = insert_session(&backend, user_id);
+        let other = insert_session(&backend, user_id);
+        let other_user = insert_session(&backend, other_user_id);
+        let cached = cached(&backend);
+        for id in [current, other, other_user] {
+            cached.load_session_data(&id).await.unwrap();
+        }
+
+        cached
+            .delete_user_sessions(&user_id, &current)
+            .await
+            .unwrap();
+        assert!(cached.load_session_data(&current).await.unwrap().is_some());
+        assert!(cached.load_session_data(&other).await.unwrap().is_none());
+        assert!(
+            cached
+                .load_session_data(&other_user)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let stats = cached.session_stats();
+        assert_eq!((stats.hits, stats.misses), (2, 4));
+    }
+}
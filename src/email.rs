@@ -0,0 +1,169 @@
+/// The normalization used when the backend does not configure one.
+pub(crate) static DEFAULT_EMAIL_NORMALIZATION: EmailNormalization =
+    EmailNormalization::new();
+
+/// How email addresses are normalized before they are looked up or stored.
+///
+/// Surrounding whitespace is removed,
+/// and the domain is converted to lowercase ASCII using IDNA,
+/// so `"Alice@Bücher.Example "` becomes `"Alice@xn--bcher-kva.example"`.
+/// The local part (before the `@`) is case-sensitive by the standard,
+/// but since virtually all mail servers treat it case-insensitively,
+/// it can be converted to lowercase as well.
+#[derive(Clone, Debug)]
+pub struct EmailNormalization {
+    /// Whether to convert the local part to lowercase.
+    pub case_fold: bool,
+}
+
+impl EmailNormalization {
+    /// Create the default normalization,
+    /// which keeps the case of the local part.
+    pub const fn new() -> Self {
+        Self { case_fold: false }
+    }
+
+    /// Normalize an email address.
+    ///
+    /// If the domain is not valid, it is only converted to lowercase.
+    pub fn normalize(&self, email: &str) -> String {
+        let email = email.trim();
+        let Some((local, domain)) = email.rsplit_once('@') else {
+            return email.to_owned();
+        };
+        let local = if self.case_fold {
+            local.to_lowercase()
+        } else {
+            local.to_owned()
+        };
+        let domain = idna::domain_to_ascii(domain)
+            .unwrap_or_else(|_| domain.to_lowercase());
+        format!("{local}@{domain}")
+    }
+}
+
+impl Default for EmailNormalization {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An identifier a user logs in with.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LoginIdentifier {
+    /// A normalized email address.
+    Email(String),
+    /// A phone number, as `+` followed by digits.
+    PhoneNumber(String),
+    /// A username, with surrounding whitespace removed.
+    Username(String),
+}
+
+impl LoginIdentifier {
+    /// Parse the identifier a user entered in a login form.
+    ///
+    /// Input containing an `@` is an email address,
+    /// normalized using `normalization`.
+    /// Input starting with `+` and otherwise consisting of digits,
+    /// spaces, dashes and parentheses is a phone number.
+    /// Anything else is a username.
+    pub fn parse(input: &str, normalization: &EmailNormalization) -> Self {
+        let input = input.trim();
+        if input.contains('@') {
+            return Self::Email(normalization.normalize(input));
+        }
+        if let Some(number) = input.strip_prefix('+') {
+            let mut digits = String::from("+");
+            let mut valid = true;
+            for c in number.chars() {
+                match c {
+                    '0'..='9' => digits.push(c),
+                    ' ' | '-' | '(' | ')' => {}
+                    _ => valid = false,
+                }
+            }
+            if valid && digits.len() > 1 {
+                return Self::PhoneNumber(digits);
+            }
+        }
+        Self::Username(input.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEEP_CASE: EmailNormalization = EmailNormalization::new();
+    const CASE_FOLD: EmailNormalization =
+        EmailNormalization { case_fold: true };
+
+    #[test]
+    fn normalize() {
+        let cases = [
+            (
+                "alice@example.com",
+                "alice@example.com",
+                "alice@example.com",
+            ),
+            (
+                " alice@example.com\t",
+                "alice@example.com",
+                "alice@example.com",
+            ),
+            (
+                "Alice@Example.COM",
+                "Alice@example.com",
+                "alice@example.com",
+            ),
+            (
+                "Alice@B\u{fc}cher.Example ",
+                "Alice@xn--bcher-kva.example",
+                "alice@xn--bcher-kva.example",
+            ),
+            (
+                "\u{c9}lise@B\u{dc}CHER.example",
+                "\u{c9}lise@xn--bcher-kva.example",
+                "\u{e9}lise@xn--bcher-kva.example",
+            ),
+            // Only the last `@` separates the domain.
+            (
+                "\"A@B\"@Example.com",
+                "\"A@B\"@example.com",
+                "\"a@b\"@example.com",
+            ),
+            // Input without an `@` is only trimmed.
+            (" Alice ", "Alice", "Alice"),
+        ];
+        for (email, keep_case, case_fold) in cases {
+            assert_eq!(KEEP_CASE.normalize(email), keep_case, "{email:?}");
+            assert_eq!(CASE_FOLD.normalize(email), case_fold, "{email:?}");
+        }
+    }
+
+    #[test]
+    fn parse_identifier() {
+        use LoginIdentifier::{Email, PhoneNumber, Username};
+
+        type Variant = fn(String) -> LoginIdentifier;
+        let cases: [(&str, Variant, &str); _] = [
+            ("Alice@Example.com", Email, "Alice@example.com"),
+            (" alice@example.com ", Email, "alice@example.com"),
+            ("+31 6 1234-5678", PhoneNumber, "+31612345678"),
+            ("+1 (555) 010-0000", PhoneNumber, "+15550100000"),
+            ("+", Username, "+"),
+            ("+31 6 12a", Username, "+31 6 12a"),
+            ("0612345678", Username, "0612345678"),
+            (" alice ", Username, "alice"),
+            ("Alice", Username, "Alice"),
+        ];
+        for (input, variant, value) in cases {
+            let parsed = LoginIdentifier::parse(input, &KEEP_CASE);
+            assert_eq!(parsed, variant(value.to_owned()), "{input:?}");
+        }
+        assert_eq!(
+            LoginIdentifier::parse("Alice@Example.com", &CASE_FOLD),
+            Email("alice@example.com".to_owned()),
+        );
+    }
+}
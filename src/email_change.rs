@@ -0,0 +1,486 @@
+use std::time::{Duration, SystemTime};
+
+use crate::token_utils::{random_token, sha256};
+use crate::{
+    AuditEvent, Backend, ComposedBackend, ComposedError, Session, SessionStore,
+    User, UserSessionsBackend, UserStore,
+};
+
+/// The number of random bytes in an email change token.
+const EMAIL_CHANGE_TOKEN_BYTES: usize = 32;
+
+/// A single-use token to confirm or cancel an email change,
+/// typically sent to the user by email as part of a link.
+///
+/// The token itself is never stored by the backend, only its hash.
+pub struct EmailChangeToken(String);
+
+impl EmailChangeToken {
+    /// Generate a new random token.
+    fn generate() -> Self {
+        Self(random_token::<EMAIL_CHANGE_TOKEN_BYTES>(""))
+    }
+
+    /// Get the token as a string.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the hash of this token, as stored by the backend.
+    pub fn hash(&self) -> EmailChangeTokenHash {
+        EmailChangeTokenHash::new(&self.0)
+    }
+}
+
+impl std::fmt::Debug for EmailChangeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EmailChangeToken([...])")
+    }
+}
+
+/// The SHA-256 hash of an [`EmailChangeToken`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct EmailChangeTokenHash(pub [u8; 32]);
+
+impl EmailChangeTokenHash {
+    fn new(token: &str) -> Self {
+        Self(sha256(token))
+    }
+}
+
+/// A pending email change as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct EmailChangeRecord<UserId> {
+    /// The hash of the token confirming the change.
+    pub hash: EmailChangeTokenHash,
+    /// The hash of the token cancelling the change.
+    pub cancel_hash: EmailChangeTokenHash,
+    /// The user whose email address changes.
+    pub user_id: UserId,
+    /// The email address of the user when the change was requested.
+    pub old_email: String,
+    /// The new email address, normalized.
+    pub new_email: String,
+    /// When the change expires.
+    pub expires_at: SystemTime,
+}
+
+/// A requested email change, returned by [`request_email_change()`].
+#[derive(Debug)]
+pub struct EmailChangeRequest {
+    /// The current email address of the user.
+    pub old_email: String,
+    /// The new email address, normalized.
+    pub new_email: String,
+    /// The token confirming the change,
+    /// which should be sent to the new email address.
+    pub token: EmailChangeToken,
+    /// The token cancelling the change,
+    /// which should be sent to the current email address.
+    pub cancel_token: EmailChangeToken,
+}
+
+/// The error returned when changing the email address fails.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EmailChangeError {
+    /// The user logged into the session did not authenticate recently,
+    /// see [`Session::require_recent_auth()`].
+    ReauthenticationRequired,
+    /// The new email address equals the current one.
+    Unchanged,
+    /// The token is unknown, used or expired,
+    /// or the email address of the user changed in the meantime.
+    InvalidToken,
+    /// Another user already has the new email address.
+    EmailTaken,
+}
+
+impl std::fmt::Display for EmailChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ReauthenticationRequired => "reauthentication required",
+            Self::Unchanged => "email address unchanged",
+            Self::InvalidToken => "invalid token",
+            Self::EmailTaken => "email address taken",
+        })
+    }
+}
+
+impl std::error::Error for EmailChangeError {}
+
+/// The interface for a backend that supports changing email addresses.
+pub trait EmailChangeBackend: Backend {
+    /// Get how long email changes remain pending.
+    fn email_change_ttl(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    /// Store a new pending email change.
+    fn store_email_change(
+        &self,
+        change: &EmailChangeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load and remove a pending email change by the hash of its token.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_email_change(
+        &self,
+        hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Load and remove a pending email change
+    /// by the hash of its cancel token.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_email_change_by_cancel_hash(
+        &self,
+        cancel_hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Update the email address of a user.
+    ///
+    /// The email address has been normalized,
+    /// see [`email_normalization()`](Backend::email_normalization).
+    /// If the user does not exist (anymore),
+    /// or if another user already has this email address,
+    /// this returns `false`.
+    fn update_user_email(
+        &self,
+        id: &<Self::User as User>::Id,
+        email: &str,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete all pending email changes of a user.
+    fn delete_user_email_changes(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that supports changing email addresses.
+///
+/// See [`EmailChangeBackend`].
+pub trait EmailChangeStore: UserStore {
+    /// Get how long email changes remain pending.
+    fn email_change_ttl(&self) -> Duration {
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    /// Store a new pending email change.
+    fn store_email_change(
+        &self,
+        change: &EmailChangeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load and remove a pending email change by the hash of its token.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_email_change(
+        &self,
+        hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Load and remove a pending email change
+    /// by the hash of its cancel token.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_email_change_by_cancel_hash(
+        &self,
+        cancel_hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Update the email address of a user.
+    ///
+    /// See [`EmailChangeBackend::update_user_email()`].
+    fn update_user_email(
+        &self,
+        id: &<Self::User as User>::Id,
+        email: &str,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete all pending email changes of a user.
+    fn delete_user_email_changes(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> EmailChangeBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: EmailChangeStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn email_change_ttl(&self) -> Duration {
+        self.users.email_change_ttl()
+    }
+
+    fn store_email_change(
+        &self,
+        change: &EmailChangeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_email_change(change);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn take_email_change(
+        &self,
+        hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.take_email_change(hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn take_email_change_by_cancel_hash(
+        &self,
+        cancel_hash: &EmailChangeTokenHash,
+    ) -> future!(Output = Result<Option<EmailChangeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.take_email_change_by_cancel_hash(cancel_hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn update_user_email(
+        &self,
+        id: &<Self::User as User>::Id,
+        email: &str,
+    ) -> future!(Output = Result<bool, Error>) {
+        let future = self.users.update_user_email(id, email);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn delete_user_email_changes(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.delete_user_email_changes(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// Request changing the email address of the user logged into the session.
+///
+/// The user must have authenticated within the last `max_age`,
+/// see [`Session::require_recent_auth()`].
+/// The new email address is normalized,
+/// see [`Backend::email_normalization()`].
+/// The email address only changes once the change is confirmed
+/// using [`confirm_email_change()`] with the token sent to the new address.
+/// The cancel token should be sent to the current address,
+/// so that the owner can stop a change they did not request
+/// using [`cancel_email_change()`].
+/// Previously requested changes of the user that are still pending
+/// are replaced by this one.
+///
+/// To prevent enumerating email addresses,
+/// this does not check whether another user has the new address;
+/// this is only reported when confirming the change.
+pub async fn request_email_change<B: EmailChangeBackend>(
+    session: &mut Session<B>,
+    new_email: &str,
+    max_age: Duration,
+) -> Result<Result<EmailChangeRequest, EmailChangeError>, B::Error> {
+    if session.require_recent_auth(max_age).await?.is_err() {
+        return Ok(Err(EmailChangeError::ReauthenticationRequired));
+    }
+    let Some(user) = session.user().await? else {
+        return Ok(Err(EmailChangeError::ReauthenticationRequired));
+    };
+    let backend = &session.backend;
+    let new_email = backend.email_normalization().normalize(new_email);
+    if new_email == user.email() {
+        return Ok(Err(EmailChangeError::Unchanged));
+    }
+    let token = EmailChangeToken::generate();
+    let cancel_token = EmailChangeToken::generate();
+    let change = EmailChangeRecord {
+        hash: token.hash(),
+        cancel_hash: cancel_token.hash(),
+        user_id: user.id().clone(),
+        old_email: user.email().to_owned(),
+        new_email,
+        expires_at: SystemTime::now() + backend.email_change_ttl(),
+    };
+    backend.delete_user_email_changes(&change.user_id).await?;
+    backend.store_email_change(&change).await?;
+    Ok(Ok(EmailChangeRequest {
+        old_email: change.old_email,
+        new_email: change.new_email,
+        token,
+        cancel_token,
+    }))
+}
+
+/// Confirm an email change by the token sent to the new address,
+/// and update the email address of the user.
+///
+/// The token is consumed, even if the change fails.
+/// The user does not need to be logged into the session,
+/// since the link may be opened in a different browser.
+///
+/// Once the email address changed,
+/// other pending email changes of the user are deleted,
+/// and all other sessions of the user are logged out,
+/// since they may be held by whoever controlled the previous address.
+/// Login tokens sent to the previous address become invalid,
+/// see [`login_by_magic_link()`](crate::login_by_magic_link).
+/// To also delete the remember-me tokens of the user, use
+/// [`forget_all_remember_me_tokens()`](crate::forget_all_remember_me_tokens).
+pub async fn confirm_email_change<B>(
+    session: &mut Session<B>,
+    token: &str,
+) -> Result<Result<(), EmailChangeError>, B::Error>
+where
+    B: EmailChangeBackend + UserSessionsBackend,
+{
+    let backend = &session.backend;
+    let hash = EmailChangeTokenHash::new(token);
+    let Some(change) = backend.take_email_change(&hash).await? else {
+        return Ok(Err(EmailChangeError::InvalidToken));
+    };
+    if SystemTime::now() >= change.expires_at {
+        return Ok(Err(EmailChangeError::InvalidToken));
+    }
+    let Some(user) = backend.load_user(&change.user_id).await? else {
+        return Ok(Err(EmailChangeError::InvalidToken));
+    };
+    if !user.account_status().is_active() || user.email() != change.old_email {
+        return Ok(Err(EmailChangeError::InvalidToken));
+    }
+    if !backend
+        .update_user_email(&change.user_id, &change.new_email)
+        .await?
+    {
+        return Ok(Err(EmailChangeError::EmailTaken));
+    }
+    backend.delete_user_email_changes(&change.user_id).await?;
+    backend
+        .delete_user_sessions(&change.user_id, session.id())
+        .await?;
+    backend
+        .record_audit_event(&AuditEvent::EmailChanged {
+            user: change.user_id,
+            old_email: change.old_email,
+            new_email: change.new_email,
+        })
+        .await?;
+    Ok(Ok(()))
+}
+
+/// Cancel a pending email change by the token sent to the current address.
+///
+/// All other pending email changes of the user are cancelled as well.
+/// This returns `false` if the token is unknown, used or expired.
+pub async fn cancel_email_change<B: EmailChangeBackend>(
+    backend: &B,
+    token: &str,
+) -> Result<bool, B::Error> {
+    let hash = EmailChangeTokenHash::new(token);
+    let Some(change) = backend.take_email_change_by_cancel_hash(&hash).await?
+    else {
+        return Ok(false);
+    };
+    if SystemTime::now() >= change.expires_at {
+        return Ok(false);
+    }
+    backend.delete_user_email_changes(&change.user_id).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBackend;
+    use crate::{
+        Authenticated, forget_all_remember_me_tokens, issue_login_token,
+        login_by_magic_link, remember_me,
+    };
+
+    const MAX_AGE: Duration = Duration::from_secs(60);
+
+    async fn request(
+        session: &mut Session<TestBackend>,
+        new_email: &str,
+    ) -> EmailChangeRequest {
+        request_email_change(session, new_email, MAX_AGE)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_replaces_pending_change() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        let first = request(&mut session, "b@example.com").await;
+        let second = request(&mut session, "c@example.com").await;
+        let mut other = backend.session(None);
+        let result = confirm_email_change(&mut other, first.token.as_str());
+        assert_eq!(result.await.unwrap(), Err(EmailChangeError::InvalidToken),);
+        let result = confirm_email_change(&mut other, second.token.as_str());
+        assert_eq!(result.await.unwrap(), Ok(()));
+        assert_eq!(backend.state().users[0].email, "c@example.com");
+    }
+
+    #[tokio::test]
+    async fn confirm_revokes_credentials() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut other_session = backend.session(Some(user_id));
+        other_session.save().await.unwrap();
+        remember_me(&other_session, &Authenticated::new())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut login_token = None;
+        issue_login_token(&backend, "a@example.com", |_, token| {
+            login_token = Some(token);
+        })
+        .await
+        .unwrap();
+        let login_token = login_token.unwrap();
+
+        let mut session = backend.session(Some(user_id));
+        let change = request(&mut session, "b@example.com").await;
+        let result = confirm_email_change(&mut session, change.token.as_str());
+        assert_eq!(result.await.unwrap(), Ok(()));
+        forget_all_remember_me_tokens(&session).await.unwrap();
+        {
+            let state = backend.state();
+            assert!(!state.sessions.contains_key(other_session.id()));
+            assert!(state.remember_me_tokens.is_empty());
+        }
+
+        let mut session = backend.session(None);
+        let login = login_by_magic_link(&mut session, login_token.as_str());
+        assert!(login.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        let change = request(&mut session, "b@example.com").await;
+        assert!(
+            cancel_email_change(&backend, change.cancel_token.as_str())
+                .await
+                .unwrap()
+        );
+        assert!(
+            !cancel_email_change(&backend, change.cancel_token.as_str())
+                .await
+                .unwrap()
+        );
+        let mut session = backend.session(None);
+        let result = confirm_email_change(&mut session, change.token.as_str());
+        assert_eq!(result.await.unwrap(), Err(EmailChangeError::InvalidToken),);
+        assert_eq!(backend.state().users[0].email, "a@example.com");
+    }
+}
@@ -1,11 +1,27 @@
-use crate::{Authenticated, Backend, Session, User, ValidPassword};
+use crate::{
+    AuditEvent, Authenticated, Backend, BadPassword, ChangePasswordError,
+    HashedPassword, Impersonating, ImpersonationError, LoginIdentifier,
+    RegistrationBackend, Session, User, UserSessionsBackend, ValidPassword,
+};
 
-pub async fn login_by_password<B: Backend>(
-    session: &mut Session<B>,
-    email: &str,
+/// Authenticate a user by their login identifier and password,
+/// without logging them into a session.
+///
+/// The identifier is typically an email address,
+/// see [`LoginIdentifier::parse()`] for the identifiers supported.
+/// This is useful for stateless authentication,
+/// such as issuing tokens to API clients.
+/// If the identifier or password is incorrect,
+/// or if the account is not [active](crate::AccountStatus::is_active),
+/// this returns `None`.
+pub async fn authenticate_by_password<B: Backend>(
+    backend: &B,
+    login: &str,
     password: &str,
-) -> Result<Option<Authenticated>, B::Error> {
-    let Some(user) = session.backend.load_user_by_email(email).await? else {
+) -> Result<Option<(B::User, Authenticated)>, B::Error> {
+    let identifier =
+        LoginIdentifier::parse(login, backend.email_normalization());
+    let Some(user) = backend.load_user_by_identifier(&identifier).await? else {
         return Ok(None);
     };
     let Some(hashed_password) = user.hashed_password() else {
@@ -14,26 +30,472 @@ pub async fn login_by_password<B: Backend>(
     let Some(auth) = hashed_password.verify(password) else {
         return Ok(None);
     };
+    if !user.account_status().is_active() {
+        return Ok(None);
+    }
+    Ok(Some((user, auth)))
+}
+
+/// Register a new user with an email address and password,
+/// without logging them into a session.
+///
+/// The email address is normalized,
+/// see [`Backend::email_normalization()`].
+/// The password is validated using the email address as a user input,
+/// so passwords containing the email address are considered weak.
+/// If a user with this email address already exists, this returns `None`.
+///
+/// To prevent enumerating email addresses,
+/// the response to the client must not depend on whether a user was created.
+/// For example, always ask the user to confirm their email address,
+/// and notify the existing user instead.
+pub async fn register<B: RegistrationBackend>(
+    backend: &B,
+    email: &str,
+    password: String,
+) -> Result<Result<Option<B::User>, BadPassword>, B::Error> {
+    let email = backend.email_normalization().normalize(email);
+    let policy = backend.password_policy();
+    let password = match ValidPassword::new(password, policy, &[&email]).await {
+        Ok(password) => password,
+        Err(e) => return Ok(Err(e)),
+    };
+    // NOTE: The password is always hashed, even if the email address
+    // is already taken, to avoid leaking this through timing.
+    let hashed_password = HashedPassword::new(&password);
+    let user = backend.create_user(&email, Some(&hashed_password)).await?;
+    Ok(Ok(user))
+}
+
+pub async fn register_and_login<B: RegistrationBackend>(
+    session: &mut Session<B>,
+    email: &str,
+    password: String,
+) -> Result<Result<Option<Authenticated>, BadPassword>, B::Error> {
+    match register(&session.backend, email, password).await? {
+        Ok(Some(user)) => {
+            session.set_user(Some(user));
+            Ok(Ok(Some(session.set_authenticated(Authenticated::new()))))
+        }
+        Ok(None) => Ok(Ok(None)),
+        Err(e) => Ok(Err(e)),
+    }
+}
+
+pub async fn login_by_password<B: Backend>(
+    session: &mut Session<B>,
+    login: &str,
+    password: &str,
+) -> Result<Option<Authenticated>, B::Error> {
+    let Some((user, auth)) =
+        authenticate_by_password(&session.backend, login, password).await?
+    else {
+        return Ok(None);
+    };
     session.set_user(Some(user));
-    Ok(Some(auth))
+    Ok(Some(session.set_authenticated(auth)))
 }
 
 pub async fn update_user_password<B: Backend>(
     session: &mut Session<B>,
     password: &ValidPassword,
-) -> Result<(), B::Error> {
+) -> Result<Result<(), ChangePasswordError>, B::Error> {
+    if session.impersonator().is_some() {
+        return Ok(Err(ChangePasswordError::Impersonating));
+    }
+    let Some(user) = session.user().await? else {
+        return Ok(Ok(()));
+    };
+    let user_id = user.id().clone();
+    let depth = session.backend.password_policy().history_depth;
+    let mut previous = None;
+    if depth > 0 {
+        previous = user.hashed_password().cloned();
+        let history = session
+            .backend
+            .load_password_history(&user_id, depth - 1)
+            .await?;
+        if previous
+            .iter()
+            .chain(&history)
+            .any(|hash| hash.matches(password))
+        {
+            return Ok(Err(ChangePasswordError::BadPassword(
+                BadPassword::Reused,
+            )));
+        }
+    }
+    let hashed_password = HashedPassword::new(password);
+    session
+        .backend
+        .update_user_password(&user_id, &hashed_password)
+        .await?;
+    if let Some(previous) = previous {
+        session
+            .backend
+            .add_password_history(&user_id, &previous, depth - 1)
+            .await?;
+    }
+    session.needs_save();
+    if let Some(user) = session.user.get_mut() {
+        user.set_hashed_password(Some(hashed_password));
+    }
+    Ok(Ok(()))
+}
+
+pub async fn change_password<B: UserSessionsBackend>(
+    session: &mut Session<B>,
+    current: &str,
+    new: String,
+    revoke_other_sessions: bool,
+) -> Result<Result<(), ChangePasswordError>, B::Error> {
+    if session.impersonator().is_some() {
+        return Ok(Err(ChangePasswordError::Impersonating));
+    }
+    let Some(user) = session.user().await? else {
+        return Ok(Err(ChangePasswordError::NotAuthenticated));
+    };
+    let Some(hashed_password) = user.hashed_password().cloned() else {
+        return Ok(Err(ChangePasswordError::IncorrectPassword));
+    };
+    let Some(auth) = hashed_password.verify(current) else {
+        return Ok(Err(ChangePasswordError::IncorrectPassword));
+    };
+    let email = user.email().to_owned();
+    let policy = session.backend.password_policy();
+    let password = match ValidPassword::new(new, policy, &[&email]).await {
+        Ok(password) => password,
+        Err(e) => return Ok(Err(ChangePasswordError::BadPassword(e))),
+    };
+    // NOTE: This compares the normalized passwords,
+    // see `ValidPassword::new()`.
+    if hashed_password.matches(&password) {
+        return Ok(Err(ChangePasswordError::SamePassword));
+    }
+    if let Err(e) = update_user_password(session, &password).await? {
+        return Ok(Err(e));
+    }
+    if revoke_other_sessions {
+        // NOTE: This cannot fail, since `update_user_password()` already
+        // checked that no user is being impersonated.
+        let _ = logout_other_sessions(session).await?;
+    }
+    session.set_authenticated(auth);
+    Ok(Ok(()))
+}
+
+pub async fn reauthenticate<B: Backend>(
+    session: &mut Session<B>,
+    password: &str,
+) -> Result<Option<Authenticated>, B::Error> {
+    let Some(user) = session.user().await? else {
+        return Ok(None);
+    };
+    let Some(auth) = user
+        .hashed_password()
+        .and_then(|hashed_password| hashed_password.verify(password))
+    else {
+        return Ok(None);
+    };
+    Ok(Some(session.set_authenticated(auth)))
+}
+
+pub async fn logout_other_sessions<B: UserSessionsBackend>(
+    session: &Session<B>,
+) -> Result<Result<(), Impersonating>, B::Error> {
+    if session.impersonator().is_some() {
+        return Ok(Err(Impersonating));
+    }
     if let Some(user_id) = session.user.id() {
-        let hashed_password = super::password::HashedPassword::new(password);
         session
             .backend
-            .update_user_password(user_id, &hashed_password)
+            .delete_user_sessions(user_id, session.id())
             .await?;
-        session.needs_save();
-        if let Some(user) = session.user.get_mut() {
-            user.set_hashed_password(Some(hashed_password));
-        }
-        Ok(())
-    } else {
-        Ok(())
+    }
+    Ok(Ok(()))
+}
+
+pub async fn impersonate<B: Backend>(
+    session: &mut Session<B>,
+    user_id: &<B::User as User>::Id,
+) -> Result<Result<(), ImpersonationError>, B::Error> {
+    let Some(impersonator) = session.user.id().cloned() else {
+        return Ok(Err(ImpersonationError::NotAuthenticated));
+    };
+    if session.impersonator().is_some() {
+        return Ok(Err(ImpersonationError::AlreadyImpersonating));
+    }
+    let Some(user) = session.backend.load_user(user_id).await? else {
+        return Ok(Err(ImpersonationError::UnknownUser));
+    };
+    let event = AuditEvent::ImpersonationStarted {
+        impersonator: impersonator.clone(),
+        user: user_id.clone(),
+    };
+    session.backend.record_audit_event(&event).await?;
+    session.set_user(Some(user));
+    session.set_impersonator(Some(impersonator));
+    Ok(Ok(()))
+}
+
+pub async fn stop_impersonating<B: Backend>(
+    session: &mut Session<B>,
+) -> Result<bool, B::Error> {
+    let Some(impersonator) = session.impersonator().cloned() else {
+        return Ok(false);
+    };
+    if let Some(user_id) = session.user.id() {
+        let event = AuditEvent::ImpersonationStopped {
+            impersonator: impersonator.clone(),
+            user: user_id.clone(),
+        };
+        session.backend.record_audit_event(&event).await?;
+    }
+    session.set_impersonator(None);
+    session.set_user_id(Some(impersonator));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::TestBackend;
+    use crate::{
+        AccountStatus, ApiTokenError, PasswordPolicy, create_api_token,
+    };
+
+    async fn backend_with_user(password: &str) -> (TestBackend, u64) {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let policy = PasswordPolicy::default();
+        let password = ValidPassword::new(password.to_owned(), &policy, &[])
+            .await
+            .unwrap();
+        backend.state().users[0].hashed_password =
+            Some(HashedPassword::new(&password));
+        (backend, user_id)
+    }
+
+    #[tokio::test]
+    async fn change_password() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let mut session = backend.session(Some(user_id));
+        let result = session
+            .change_password(
+                "first passphrase",
+                "second passphrase".to_owned(),
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(result.is_ok());
+        let hashed_password = backend.state().users[0].hashed_password.clone();
+        assert!(
+            hashed_password
+                .unwrap()
+                .verify("second passphrase")
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn change_password_revoke_other_sessions() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let mut other = backend.session(Some(user_id));
+        other.force_save().await.unwrap();
+        let mut session = backend.session(Some(user_id));
+        session.force_save().await.unwrap();
+
+        session
+            .change_password(
+                "first passphrase",
+                "second passphrase".to_owned(),
+                false,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(backend.state().sessions.contains_key(other.id()));
+
+        session
+            .change_password(
+                "second passphrase",
+                "third passphrase".to_owned(),
+                true,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let state = backend.state();
+        assert!(!state.sessions.contains_key(other.id()));
+        assert!(state.sessions.contains_key(session.id()));
+    }
+
+    #[tokio::test]
+    async fn login_inactive_user() {
+        let (backend, _) = backend_with_user("first passphrase").await;
+        let mut session = backend.session(None);
+        let auth = session
+            .login_by_password("a@example.com", "first passphrase")
+            .await
+            .unwrap();
+        assert!(auth.is_some());
+
+        backend.state().users[0].status = AccountStatus::Locked;
+        let mut session = backend.session(None);
+        let auth = session
+            .login_by_password("a@example.com", "first passphrase")
+            .await
+            .unwrap();
+        assert!(auth.is_none());
+        assert!(!session.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn update_password_inactive_user() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let mut session = backend.session(Some(user_id));
+        backend.state().users[0].status = AccountStatus::Disabled;
+        let policy = PasswordPolicy::default();
+        let password =
+            ValidPassword::new("second passphrase".to_owned(), &policy, &[])
+                .await
+                .unwrap();
+        session
+            .update_user_password(&password)
+            .await
+            .unwrap()
+            .unwrap();
+        let hashed_password = backend.state().users[0].hashed_password.clone();
+        assert!(
+            hashed_password
+                .unwrap()
+                .verify("first passphrase")
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn change_password_incorrect() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let mut session = backend.session(Some(user_id));
+        let result = session
+            .change_password(
+                "wrong passphrase",
+                "second passphrase".to_owned(),
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            result,
+            Err(ChangePasswordError::IncorrectPassword)
+        ));
+    }
+
+    #[tokio::test]
+    async fn change_password_same_after_normalization() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let mut session = backend.session(Some(user_id));
+        // The ligature "ﬁ" normalizes to "fi".
+        let result = session
+            .change_password(
+                "first passphrase",
+                "\u{fb01}rst passphrase".to_owned(),
+                false,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(ChangePasswordError::SamePassword)));
+    }
+
+    #[tokio::test]
+    async fn impersonate() {
+        let backend = TestBackend::default();
+        let admin_id = backend.add_user("admin@example.com");
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(None);
+        let result = session.impersonate(&user_id).await.unwrap();
+        assert!(matches!(result, Err(ImpersonationError::NotAuthenticated)));
+
+        let mut session = backend.session(Some(admin_id));
+        let result = session.impersonate(&42).await.unwrap();
+        assert!(matches!(result, Err(ImpersonationError::UnknownUser)));
+        session.impersonate(&user_id).await.unwrap().unwrap();
+        assert_eq!(session.user.id(), Some(&user_id));
+        assert_eq!(session.impersonator(), Some(&admin_id));
+        let result = session.impersonate(&admin_id).await.unwrap();
+        assert!(matches!(
+            result,
+            Err(ImpersonationError::AlreadyImpersonating)
+        ));
+
+        assert!(session.stop_impersonating().await.unwrap());
+        assert_eq!(session.user.id(), Some(&admin_id));
+        assert_eq!(session.impersonator(), None);
+        let result = session.require_recent_auth(Duration::from_secs(60));
+        assert!(result.await.unwrap().is_err());
+        assert!(!session.stop_impersonating().await.unwrap());
+
+        assert!(matches!(
+            backend.state().audit_events.as_slice(),
+            [
+                AuditEvent::ImpersonationStarted {
+                    impersonator: 1,
+                    user: 2,
+                },
+                AuditEvent::ImpersonationStopped {
+                    impersonator: 1,
+                    user: 2,
+                },
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn impersonate_blocks_sensitive_actions() {
+        let (backend, user_id) = backend_with_user("first passphrase").await;
+        let admin_id = backend.add_user("admin@example.com");
+        let mut other = backend.session(Some(user_id));
+        other.force_save().await.unwrap();
+        let mut session = backend.session(Some(admin_id));
+        session.impersonate(&user_id).await.unwrap().unwrap();
+
+        let policy = PasswordPolicy::default();
+        let password =
+            ValidPassword::new("second passphrase".to_owned(), &policy, &[])
+                .await
+                .unwrap();
+        let result = session.update_user_password(&password).await.unwrap();
+        assert!(matches!(result, Err(ChangePasswordError::Impersonating)));
+        let result = session
+            .change_password(
+                "first passphrase",
+                "second passphrase".to_owned(),
+                true,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(ChangePasswordError::Impersonating)));
+        let hashed_password = backend.state().users[0].hashed_password.clone();
+        assert!(
+            hashed_password
+                .unwrap()
+                .verify("first passphrase")
+                .is_some()
+        );
+
+        let result = session.logout_other_sessions().await.unwrap();
+        assert!(result.is_err());
+        assert!(backend.state().sessions.contains_key(other.id()));
+
+        let result =
+            create_api_token(&session, "token".to_owned(), vec![], None)
+                .await
+                .unwrap();
+        assert_eq!(result.unwrap_err(), ApiTokenError::Impersonating);
+        assert!(backend.state().api_tokens.is_empty());
     }
 }
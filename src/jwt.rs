@@ -0,0 +1,647 @@
+//! Stateless authentication using JWT access tokens.
+//!
+//! After authenticating a user, for example through
+//! [`authenticate_by_password()`](crate::authenticate_by_password),
+//! [`issue_tokens()`] creates a short-lived signed access token
+//! together with a long-lived refresh token.
+//! The access token can be validated without accessing the backend,
+//! using [`JwtConfig::validate_access_token()`].
+//!
+//! Refresh tokens are single-use; each use returns a new token pair
+//! through [`refresh_tokens()`].
+//! All refresh tokens descending from the same login form a family.
+//! If a refresh token is used more than once, it was likely stolen,
+//! and the whole family is revoked.
+//! When the password of a user changes,
+//! use [`revoke_user_tokens()`] to revoke all their families.
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::token_utils::{random_token, sha256};
+use crate::{
+    Authenticated, Backend, ComposedBackend, ComposedError, SessionStore, User,
+    UserStore,
+};
+
+/// The number of random bytes in a refresh token.
+const REFRESH_TOKEN_BYTES: usize = 32;
+
+/// The configuration for signing and validating access tokens.
+#[derive(Clone)]
+pub struct JwtConfig {
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    issuer: Option<String>,
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+}
+
+impl JwtConfig {
+    fn new(
+        algorithm: Algorithm,
+        encoding_key: EncodingKey,
+        decoding_key: DecodingKey,
+    ) -> Self {
+        Self {
+            algorithm,
+            encoding_key,
+            decoding_key,
+            issuer: None,
+            access_token_ttl: Duration::from_secs(15 * 60),
+            refresh_token_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    /// Sign tokens using HMAC with SHA-256 and a shared secret.
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self::new(
+            Algorithm::HS256,
+            EncodingKey::from_secret(secret),
+            DecodingKey::from_secret(secret),
+        )
+    }
+
+    /// Sign tokens using Ed25519, with PEM-encoded keys.
+    pub fn ed25519(
+        private_key_pem: &[u8],
+        public_key_pem: &[u8],
+    ) -> Result<Self, jsonwebtoken::errors::Error> {
+        Ok(Self::new(
+            Algorithm::EdDSA,
+            EncodingKey::from_ed_pem(private_key_pem)?,
+            DecodingKey::from_ed_pem(public_key_pem)?,
+        ))
+    }
+
+    /// Sign tokens using ECDSA with P-256 and SHA-256, with PEM-encoded keys.
+    pub fn es256(
+        private_key_pem: &[u8],
+        public_key_pem: &[u8],
+    ) -> Result<Self, jsonwebtoken::errors::Error> {
+        Ok(Self::new(
+            Algorithm::ES256,
+            EncodingKey::from_ec_pem(private_key_pem)?,
+            DecodingKey::from_ec_pem(public_key_pem)?,
+        ))
+    }
+
+    /// Set the issuer of access tokens.
+    ///
+    /// When set, access tokens with a different issuer are rejected.
+    pub fn with_issuer(mut self, issuer: String) -> Self {
+        self.issuer = Some(issuer);
+        self
+    }
+
+    /// Set how long access tokens are valid.
+    ///
+    /// The default is 15 minutes.
+    pub fn with_access_token_ttl(mut self, ttl: Duration) -> Self {
+        self.access_token_ttl = ttl;
+        self
+    }
+
+    /// Set how long refresh tokens are valid.
+    ///
+    /// The default is 30 days.
+    pub fn with_refresh_token_ttl(mut self, ttl: Duration) -> Self {
+        self.refresh_token_ttl = ttl;
+        self
+    }
+
+    fn issue_access_token<Id: std::fmt::Display>(
+        &self,
+        user_id: &Id,
+        now: SystemTime,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let issued_at = unix_time(now);
+        let claims = Claims {
+            sub: user_id.to_string(),
+            iat: issued_at,
+            exp: issued_at + self.access_token_ttl.as_secs(),
+            iss: self.issuer.clone(),
+        };
+        jsonwebtoken::encode(
+            &Header::new(self.algorithm),
+            &claims,
+            &self.encoding_key,
+        )
+    }
+
+    /// Validate an access token.
+    ///
+    /// This returns `None` if the token is malformed, has an invalid signature,
+    /// is expired or was issued by a different issuer.
+    pub fn validate_access_token<Id: FromStr>(
+        &self,
+        token: &str,
+    ) -> Option<AccessToken<Id>> {
+        let mut validation = Validation::new(self.algorithm);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        let data = jsonwebtoken::decode::<Claims>(
+            token,
+            &self.decoding_key,
+            &validation,
+        )
+        .ok()?;
+        Some(AccessToken {
+            user_id: data.claims.sub.parse().ok()?,
+            expires_at: UNIX_EPOCH + Duration::from_secs(data.claims.exp),
+        })
+    }
+}
+
+impl std::fmt::Debug for JwtConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtConfig")
+            .field("algorithm", &self.algorithm)
+            .field("issuer", &self.issuer)
+            .field("access_token_ttl", &self.access_token_ttl)
+            .field("refresh_token_ttl", &self.refresh_token_ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The claims of an access token.
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+}
+
+/// A validated access token.
+#[derive(Clone, Debug)]
+pub struct AccessToken<Id> {
+    /// The id of the user the token was issued to.
+    pub user_id: Id,
+    /// When the token expires.
+    pub expires_at: SystemTime,
+}
+
+/// An access token together with a refresh token.
+pub struct TokenPair {
+    /// The signed access token.
+    pub access_token: String,
+    /// The refresh token, to obtain a new token pair.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub refresh_token: String,
+    /// How long the access token is valid.
+    pub expires_in: Duration,
+}
+
+impl std::fmt::Debug for TokenPair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenPair([...])")
+    }
+}
+
+/// The SHA-256 hash of a refresh token.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RefreshTokenHash(pub [u8; 32]);
+
+/// A refresh token as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct RefreshTokenRecord<UserId> {
+    /// The hash of the token.
+    pub hash: RefreshTokenHash,
+    /// The family of the token,
+    /// shared by all tokens descending from the same login.
+    pub family: uuid::Uuid,
+    /// The user the token was issued to.
+    pub user_id: UserId,
+    /// When the token expires.
+    pub expires_at: SystemTime,
+    /// Whether the token was used to obtain a new token pair.
+    pub used: bool,
+    /// Whether the token family was revoked.
+    pub revoked: bool,
+}
+
+/// The interface for a backend that stores refresh tokens.
+pub trait RefreshTokenBackend: Backend {
+    /// Store a new refresh token.
+    fn store_refresh_token(
+        &self,
+        token: &RefreshTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a refresh token by its hash.
+    fn load_refresh_token(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<Option<RefreshTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Mark a refresh token as used.
+    ///
+    /// This must be atomic: if the token was already marked as used,
+    /// nothing is changed and this returns `false`.
+    fn mark_refresh_token_used(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Revoke all refresh tokens in a family.
+    fn revoke_refresh_token_family(
+        &self,
+        family: &uuid::Uuid,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Revoke all refresh tokens of a user.
+    ///
+    /// See [`revoke_user_tokens()`].
+    fn revoke_user_refresh_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that stores refresh tokens.
+///
+/// See [`RefreshTokenBackend`].
+pub trait RefreshTokenStore: UserStore {
+    /// Store a new refresh token.
+    fn store_refresh_token(
+        &self,
+        token: &RefreshTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a refresh token by its hash.
+    fn load_refresh_token(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<Option<RefreshTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Mark a refresh token as used.
+    fn mark_refresh_token_used(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Revoke all refresh tokens in a family.
+    fn revoke_refresh_token_family(
+        &self,
+        family: &uuid::Uuid,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Revoke all refresh tokens of a user.
+    fn revoke_user_refresh_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> RefreshTokenBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: RefreshTokenStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn store_refresh_token(
+        &self,
+        token: &RefreshTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_refresh_token(token);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_refresh_token(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<Option<RefreshTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.load_refresh_token(hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn mark_refresh_token_used(
+        &self,
+        hash: &RefreshTokenHash,
+    ) -> future!(Output = Result<bool, Error>) {
+        let future = self.users.mark_refresh_token_used(hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn revoke_refresh_token_family(
+        &self,
+        family: &uuid::Uuid,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.revoke_refresh_token_family(family);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn revoke_user_refresh_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.revoke_user_refresh_tokens(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// The error returned when issuing tokens fails.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The backend returned an error.
+    Backend(E),
+    /// The access token could not be signed.
+    Jwt(jsonwebtoken::errors::Error),
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Backend(e) => e.fmt(f),
+            Self::Jwt(e) => write!(f, "failed to sign access token: {e}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend(e) => Some(e),
+            Self::Jwt(e) => Some(e),
+        }
+    }
+}
+
+async fn issue_tokens_in_family<B: RefreshTokenBackend>(
+    backend: &B,
+    config: &JwtConfig,
+    user_id: &<B::User as User>::Id,
+    family: uuid::Uuid,
+) -> Result<TokenPair, Error<B::Error>>
+where
+    <B::User as User>::Id: std::fmt::Display,
+{
+    let now = SystemTime::now();
+    let access_token = config
+        .issue_access_token(user_id, now)
+        .map_err(Error::Jwt)?;
+    let refresh_token = random_token::<REFRESH_TOKEN_BYTES>("");
+    let record = RefreshTokenRecord {
+        hash: RefreshTokenHash(sha256(&refresh_token)),
+        family,
+        user_id: user_id.clone(),
+        expires_at: now + config.refresh_token_ttl,
+        used: false,
+        revoked: false,
+    };
+    backend
+        .store_refresh_token(&record)
+        .await
+        .map_err(Error::Backend)?;
+    Ok(TokenPair {
+        access_token,
+        refresh_token,
+        expires_in: config.access_token_ttl,
+    })
+}
+
+/// Issue a new token pair to an authenticated user.
+///
+/// This takes the user together with the proof of authentication,
+/// as returned by
+/// [`authenticate_by_password()`](crate::authenticate_by_password),
+/// and starts a new refresh token family.
+pub async fn issue_tokens<B: RefreshTokenBackend>(
+    backend: &B,
+    config: &JwtConfig,
+    (user, _): &(B::User, Authenticated),
+) -> Result<TokenPair, Error<B::Error>>
+where
+    <B::User as User>::Id: std::fmt::Display,
+{
+    let family = uuid::Uuid::new_v4();
+    issue_tokens_in_family(backend, config, user.id(), family).await
+}
+
+/// Exchange a refresh token for a new token pair.
+///
+/// This returns `None` if the refresh token is unknown, expired or revoked,
+/// or if the user it was issued to does not exist (anymore)
+/// or is not [active](crate::AccountStatus::is_active).
+/// If the refresh token was already used before,
+/// the whole token family is revoked and this returns `None`.
+pub async fn refresh_tokens<B: RefreshTokenBackend>(
+    backend: &B,
+    config: &JwtConfig,
+    refresh_token: &str,
+) -> Result<Option<TokenPair>, Error<B::Error>>
+where
+    <B::User as User>::Id: std::fmt::Display,
+{
+    let hash = RefreshTokenHash(sha256(refresh_token));
+    let Some(record) = backend
+        .load_refresh_token(&hash)
+        .await
+        .map_err(Error::Backend)?
+    else {
+        return Ok(None);
+    };
+    if record.revoked || record.expires_at <= SystemTime::now() {
+        return Ok(None);
+    }
+    let marked = !record.used
+        && backend
+            .mark_refresh_token_used(&hash)
+            .await
+            .map_err(Error::Backend)?;
+    if !marked {
+        // NOTE: This token was used before, so it was likely stolen.
+        // Since we cannot tell the legitimate user from the attacker,
+        // we revoke all tokens descending from the same login.
+        backend
+            .revoke_refresh_token_family(&record.family)
+            .await
+            .map_err(Error::Backend)?;
+        return Ok(None);
+    }
+    if !backend
+        .load_user(&record.user_id)
+        .await
+        .map_err(Error::Backend)?
+        .is_some_and(|user| user.account_status().is_active())
+    {
+        return Ok(None);
+    }
+    let tokens =
+        issue_tokens_in_family(backend, config, &record.user_id, record.family)
+            .await?;
+    Ok(Some(tokens))
+}
+
+/// Revoke a refresh token, and all tokens in its family.
+///
+/// This is useful to log out an API client.
+/// Access tokens that were already issued remain valid until they expire.
+pub async fn revoke_tokens<B: RefreshTokenBackend>(
+    backend: &B,
+    refresh_token: &str,
+) -> Result<(), B::Error> {
+    let hash = RefreshTokenHash(sha256(refresh_token));
+    if let Some(record) = backend.load_refresh_token(&hash).await? {
+        backend.revoke_refresh_token_family(&record.family).await?;
+    }
+    Ok(())
+}
+
+/// Revoke all refresh tokens of a user.
+///
+/// Call this when the credentials of the user may have been compromised,
+/// such as after [changing the password](crate::Session::change_password)
+/// or [logging out other sessions](crate::Session::logout_other_sessions).
+/// Access tokens that were already issued remain valid until they expire.
+pub async fn revoke_user_tokens<B: RefreshTokenBackend>(
+    backend: &B,
+    user_id: &<B::User as User>::Id,
+) -> Result<(), B::Error> {
+    backend.revoke_user_refresh_tokens(user_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountStatus;
+    use crate::testing::TestBackend;
+
+    fn config() -> JwtConfig {
+        JwtConfig::hs256(b"secret").with_issuer("example".to_owned())
+    }
+
+    async fn issue(backend: &TestBackend, user_id: u64) -> TokenPair {
+        let user = backend.load_user(&user_id).await.unwrap().unwrap();
+        issue_tokens(backend, &config(), &(user, Authenticated::new()))
+            .await
+            .unwrap()
+    }
+
+    async fn refresh(backend: &TestBackend, token: &str) -> Option<TokenPair> {
+        refresh_tokens(backend, &config(), token).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn access_token() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let tokens = issue(&backend, user_id).await;
+        let access_token = config()
+            .validate_access_token::<u64>(&tokens.access_token)
+            .unwrap();
+        assert_eq!(access_token.user_id, user_id);
+
+        let other = JwtConfig::hs256(b"other secret");
+        assert!(
+            other
+                .validate_access_token::<u64>(&tokens.access_token)
+                .is_none()
+        );
+        let other = JwtConfig::hs256(b"secret").with_issuer("other".to_owned());
+        assert!(
+            other
+                .validate_access_token::<u64>(&tokens.access_token)
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let first = issue(&backend, user_id).await;
+        let second = refresh(&backend, &first.refresh_token).await.unwrap();
+        assert_ne!(second.refresh_token, first.refresh_token);
+        let third = refresh(&backend, &second.refresh_token).await.unwrap();
+        assert!(
+            config()
+                .validate_access_token::<u64>(&third.access_token)
+                .is_some()
+        );
+        let state = backend.state();
+        assert_eq!(state.refresh_tokens.len(), 3);
+        assert!(
+            state
+                .refresh_tokens
+                .iter()
+                .all(|t| t.family == state.refresh_tokens[0].family)
+        );
+    }
+
+    #[tokio::test]
+    async fn reuse_revokes_family() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let other = issue(&backend, user_id).await;
+        let first = issue(&backend, user_id).await;
+        let second = refresh(&backend, &first.refresh_token).await.unwrap();
+
+        // The first token was stolen and used again.
+        assert!(refresh(&backend, &first.refresh_token).await.is_none());
+        assert!(refresh(&backend, &second.refresh_token).await.is_none());
+        assert!(refresh(&backend, &other.refresh_token).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn revoke() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let other = issue(&backend, user_id).await;
+        let tokens = issue(&backend, user_id).await;
+        revoke_tokens(&backend, &tokens.refresh_token)
+            .await
+            .unwrap();
+        assert!(refresh(&backend, &tokens.refresh_token).await.is_none());
+        assert!(refresh(&backend, &other.refresh_token).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn revoke_user() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let other_user_id = backend.add_user("b@example.com");
+        let first = issue(&backend, user_id).await;
+        let second = issue(&backend, user_id).await;
+        let other = issue(&backend, other_user_id).await;
+        revoke_user_tokens(&backend, &user_id).await.unwrap();
+        assert!(refresh(&backend, &first.refresh_token).await.is_none());
+        assert!(refresh(&backend, &second.refresh_token).await.is_none());
+        assert!(refresh(&backend, &other.refresh_token).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn refresh_inactive_user() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let tokens = issue(&backend, user_id).await;
+        backend.state().users[0].status = AccountStatus::Disabled;
+        assert!(refresh(&backend, &tokens.refresh_token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn refresh_expired() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let tokens = issue(&backend, user_id).await;
+        backend.state().refresh_tokens[0].expires_at = SystemTime::now();
+        assert!(refresh(&backend, &tokens.refresh_token).await.is_none());
+        assert!(refresh(&backend, "unknown").await.is_none());
+    }
+}
@@ -7,11 +7,31 @@
 //! ## Storage Backends
 //!
 //! - `postgres`: Enable PostgreSQL integration.
+//! - `sqlx-sqlite`: Enable the SQLite stores using `sqlx`.
+//! - `sqlx-postgres`: Enable the PostgreSQL stores using `sqlx`.
+//! - `sqlx-mysql`: Enable the MySQL stores using `sqlx`.
 //!
 //! ## Web Frameworks
 //!
 //! - `axum`: Enable Axum integration.
 //!
+//! ## Token Authentication
+//!
+//! - `jwt`: Enable issuing JWT access tokens and rotating refresh tokens.
+//! - `passkey`: Enable passwordless login using passkeys (WebAuthn).
+//! - `oidc`: Enable login with external identity providers (OpenID Connect).
+//!
+//! ## Caching
+//!
+//! - `cache`: Enable [`CachedBackend`], which caches sessions and users.
+//!
+//! ## Passwords
+//!
+//! - `password-strength`: Enable rejecting weak passwords
+//!   based on an estimate of their strength, see [`PasswordStrength`].
+//! - `breached-passwords`: Enable checking passwords against
+//!   a list of compromised passwords, see [`PasswordPolicy::breach_check`].
+//!
 //! ## Hash Algorithms
 //!
 //! This library supports multiple hash algorithms
@@ -38,23 +58,93 @@
 
 #![forbid(unsafe_code)]
 
+macro_rules! future {
+    (Output = Result<$type:ty, Error>) => {
+        impl Future<Output = Result<$type, Self::Error>> + Send
+    }
+}
+
+mod api_token;
+mod audit;
 mod backend;
+#[cfg(feature = "breached-passwords")]
+mod breach;
+#[cfg(feature = "cache")]
+mod cache;
+mod email;
+mod email_change;
 mod hash_utils;
+mod magic_link;
 mod password;
+mod remember_me;
 mod session;
+#[cfg(test)]
+mod testing;
+mod token_utils;
 mod user;
-pub use backend::{Backend, CookieSessionBackend};
+pub use api_token::{
+    ApiToken, ApiTokenBackend, ApiTokenError, ApiTokenHash, ApiTokenRecord,
+    ApiTokenStore, authenticate_api_token, create_api_token,
+};
+pub use audit::AuditEvent;
+pub use backend::{
+    Backend, ComposedBackend, ComposedError, CookieSessionBackend,
+    CookieSessionStore, RegistrationBackend, RegistrationStore, SessionStore,
+    UserSessionsBackend, UserSessionsStore, UserStore,
+};
+#[cfg(feature = "breached-passwords")]
+pub use breach::{BreachCheck, BreachedPasswordFile, BreachedPasswordSource};
+#[cfg(feature = "cache")]
+pub use cache::{CacheStats, CachedBackend};
+pub use email::{EmailNormalization, LoginIdentifier};
+pub use email_change::{
+    EmailChangeBackend, EmailChangeError, EmailChangeRecord,
+    EmailChangeRequest, EmailChangeStore, EmailChangeToken,
+    EmailChangeTokenHash, cancel_email_change, confirm_email_change,
+    request_email_change,
+};
+pub use magic_link::{
+    LoginToken, LoginTokenHash, LoginTokenRecord, MagicLinkBackend,
+    MagicLinkStore, issue_login_token, login_by_magic_link,
+};
 pub use password::{
-    Authenticated, BadPassword, HashedPassword, MAX_PASSWORD_LENGTH,
-    MIN_PASSWORD_LENGTH, ValidPassword,
+    Authenticated, BadPassword, ChangePasswordError, CharacterClass,
+    HashedPassword, MAX_PASSWORD_LENGTH, MIN_PASSWORD_LENGTH, PasswordPolicy,
+    ValidPassword,
+};
+#[cfg(feature = "password-strength")]
+pub use password::{MIN_PASSWORD_SCORE, PasswordStrength};
+pub use remember_me::{
+    InvalidRememberMeToken, RememberMeBackend, RememberMeHash, RememberMeLogin,
+    RememberMeRecord, RememberMeStore, RememberMeToken,
+    forget_all_remember_me_tokens, forget_remember_me_token,
+    login_by_remember_me, remember_me,
+};
+pub use session::{
+    Impersonating, ImpersonationError, InvalidSessionId, InvalidSessionToken,
+    MIN_SESSION_TOKEN_BYTES, ReauthenticationRequired, SaveError, Session,
+    SessionFields, SessionId, SessionInfo, SessionMetadata, SessionToken,
+    SessionUpdate,
 };
-pub use session::{Session, SessionFields, SessionId};
-pub use user::User;
+pub use user::{AccountStatus, User};
 
 mod func;
+pub use func::{authenticate_by_password, register};
 
 #[cfg(feature = "postgres")]
 mod postgres;
 
+#[cfg(feature = "sqlx")]
+pub mod sqlx;
+
+#[cfg(feature = "jwt")]
+pub mod jwt;
+
+#[cfg(feature = "passkey")]
+pub mod passkey;
+
+#[cfg(feature = "oidc")]
+pub mod oidc;
+
 #[cfg(feature = "axum")]
 pub mod axum;
@@ -0,0 +1,308 @@
+use std::time::{Duration, SystemTime};
+
+use crate::token_utils::{random_token, sha256};
+use crate::{
+    Authenticated, Backend, ComposedBackend, ComposedError, Session,
+    SessionStore, User, UserStore,
+};
+
+/// The number of random bytes in a login token.
+const LOGIN_TOKEN_BYTES: usize = 32;
+
+/// A single-use token to log in without a password,
+/// typically sent to the user by email as part of a link.
+///
+/// The token itself is never stored by the backend, only its hash.
+pub struct LoginToken(String);
+
+impl LoginToken {
+    /// Generate a new random token.
+    fn generate() -> Self {
+        Self(random_token::<LOGIN_TOKEN_BYTES>(""))
+    }
+
+    /// Get the token as a string.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the hash of this token, as stored by the backend.
+    pub fn hash(&self) -> LoginTokenHash {
+        LoginTokenHash::new(&self.0)
+    }
+}
+
+impl std::fmt::Debug for LoginToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LoginToken([...])")
+    }
+}
+
+/// The SHA-256 hash of a [`LoginToken`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct LoginTokenHash(pub [u8; 32]);
+
+impl LoginTokenHash {
+    fn new(token: &str) -> Self {
+        Self(sha256(token))
+    }
+}
+
+/// A login token as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct LoginTokenRecord<UserId> {
+    /// The hash of the token.
+    pub hash: LoginTokenHash,
+    /// The user the token logs in as.
+    pub user_id: UserId,
+    /// The email address the token was sent to.
+    pub email: String,
+    /// When the token expires.
+    pub expires_at: SystemTime,
+}
+
+/// The interface for a backend that supports passwordless login by email.
+pub trait MagicLinkBackend: Backend {
+    /// Get how long login tokens remain valid.
+    fn login_token_ttl(&self) -> Duration {
+        Duration::from_secs(15 * 60)
+    }
+
+    /// Store a new login token.
+    fn store_login_token(
+        &self,
+        token: &LoginTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load and remove a login token by its hash.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_login_token(
+        &self,
+        hash: &LoginTokenHash,
+    ) -> future!(Output = Result<Option<LoginTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Delete all login tokens of a user.
+    fn delete_user_login_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that supports passwordless login by email.
+///
+/// See [`MagicLinkBackend`].
+pub trait MagicLinkStore: UserStore {
+    /// Get how long login tokens remain valid.
+    fn login_token_ttl(&self) -> Duration {
+        Duration::from_secs(15 * 60)
+    }
+
+    /// Store a new login token.
+    fn store_login_token(
+        &self,
+        token: &LoginTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load and remove a login token by its hash.
+    ///
+    /// This must be atomic, so that each token can only be used once.
+    fn take_login_token(
+        &self,
+        hash: &LoginTokenHash,
+    ) -> future!(Output = Result<Option<LoginTokenRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Delete all login tokens of a user.
+    fn delete_user_login_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> MagicLinkBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: MagicLinkStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn login_token_ttl(&self) -> Duration {
+        self.users.login_token_ttl()
+    }
+
+    fn store_login_token(
+        &self,
+        token: &LoginTokenRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_login_token(token);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn take_login_token(
+        &self,
+        hash: &LoginTokenHash,
+    ) -> future!(Output = Result<Option<LoginTokenRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.take_login_token(hash);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn delete_user_login_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.delete_user_login_tokens(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// Issue a login token for the user with the given email address,
+/// and pass it to `deliver` to be sent to the user's email address.
+///
+/// The email address is normalized,
+/// see [`Backend::email_normalization()`].
+/// If there is no user with this email address,
+/// `deliver` is not called.
+///
+/// To prevent enumerating email addresses,
+/// the response to the client must not depend on
+/// whether a token was issued, neither in content nor in timing.
+/// So `deliver` should hand the token off,
+/// for example to a queue of emails to send,
+/// instead of sending the email before the response.
+pub async fn issue_login_token<B, F>(
+    backend: &B,
+    email: &str,
+    deliver: F,
+) -> Result<(), B::Error>
+where
+    B: MagicLinkBackend,
+    F: FnOnce(B::User, LoginToken),
+{
+    let email = backend.email_normalization().normalize(email);
+    // NOTE: The token is always generated and hashed, even if there is
+    // no user with this email address, to avoid leaking this through timing.
+    let token = LoginToken::generate();
+    let hash = token.hash();
+    let Some(user) = backend.load_user_by_email(&email).await? else {
+        return Ok(());
+    };
+    let record = LoginTokenRecord {
+        hash,
+        user_id: user.id().clone(),
+        email: user.email().to_owned(),
+        expires_at: SystemTime::now() + backend.login_token_ttl(),
+    };
+    backend.store_login_token(&record).await?;
+    deliver(user, token);
+    Ok(())
+}
+
+/// Try to log a user into the session by a login token.
+///
+/// The token is consumed, even if it has expired.
+/// If the token is unknown, used or expired,
+/// if the user it belongs to does not exist (anymore) or is not active,
+/// or if their email address changed since the token was sent,
+/// this returns `None` and the existing user (if any) remains logged in.
+/// On success, all other login tokens of the user are deleted.
+pub async fn login_by_magic_link<B: MagicLinkBackend>(
+    session: &mut Session<B>,
+    token: &str,
+) -> Result<Option<Authenticated>, B::Error> {
+    let hash = LoginTokenHash::new(token);
+    let Some(record) = session.backend.take_login_token(&hash).await? else {
+        return Ok(None);
+    };
+    if SystemTime::now() >= record.expires_at {
+        return Ok(None);
+    }
+    let Some(user) = session.backend.load_user(&record.user_id).await? else {
+        return Ok(None);
+    };
+    if !user.account_status().is_active() || user.email() != record.email {
+        return Ok(None);
+    }
+    session
+        .backend
+        .delete_user_login_tokens(&record.user_id)
+        .await?;
+    session.set_user(Some(user));
+    Ok(Some(session.set_authenticated(Authenticated::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountStatus;
+    use crate::testing::TestBackend;
+
+    async fn issue(backend: &TestBackend, email: &str) -> Option<LoginToken> {
+        let mut token = None;
+        issue_login_token(backend, email, |_, issued| token = Some(issued))
+            .await
+            .unwrap();
+        token
+    }
+
+    async fn login(backend: &TestBackend, token: &LoginToken) -> Option<u64> {
+        let mut session = backend.session(None);
+        login_by_magic_link(&mut session, token.as_str())
+            .await
+            .unwrap()?;
+        Some(session.user().await.unwrap().unwrap().id)
+    }
+
+    #[tokio::test]
+    async fn login_single_use() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = issue(&backend, "a@EXAMPLE.com").await.unwrap();
+        let other_token = issue(&backend, "a@example.com").await.unwrap();
+        assert_eq!(backend.state().login_tokens.len(), 2);
+
+        assert_eq!(login(&backend, &token).await, Some(user_id));
+        assert_eq!(login(&backend, &token).await, None);
+        // Other login tokens of the user are deleted.
+        assert!(backend.state().login_tokens.is_empty());
+        assert_eq!(login(&backend, &other_token).await, None);
+    }
+
+    #[tokio::test]
+    async fn login_expired() {
+        let backend = TestBackend::default();
+        backend.add_user("a@example.com");
+        let token = issue(&backend, "a@example.com").await.unwrap();
+        backend.state().login_tokens[0].expires_at = SystemTime::now();
+        assert_eq!(login(&backend, &token).await, None);
+        // The token is consumed.
+        assert!(backend.state().login_tokens.is_empty());
+    }
+
+    #[tokio::test]
+    async fn login_inactive_user() {
+        let backend = TestBackend::default();
+        backend.add_user("a@example.com");
+        let token = issue(&backend, "a@example.com").await.unwrap();
+        backend.state().users[0].status = AccountStatus::PendingDeletion;
+        assert_eq!(login(&backend, &token).await, None);
+    }
+
+    #[tokio::test]
+    async fn login_unknown_token() {
+        let backend = TestBackend::default();
+        backend.add_user("a@example.com");
+        let token = LoginToken::generate();
+        assert_eq!(login(&backend, &token).await, None);
+    }
+
+    #[tokio::test]
+    async fn issue_unknown_email() {
+        let backend = TestBackend::default();
+        backend.add_user("a@example.com");
+        assert!(issue(&backend, "b@example.com").await.is_none());
+        assert!(backend.state().login_tokens.is_empty());
+    }
+}
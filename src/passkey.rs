@@ -0,0 +1,1143 @@
+//! Passwordless authentication using passkeys (WebAuthn).
+//!
+//! Both the registration and the authentication ceremony consist of two steps.
+//! First, [`start_registration()`] or [`start_login()`] returns options
+//! to pass to `navigator.credentials.create()` or `navigator.credentials.get()`
+//! in the browser, and keeps the challenge in the session.
+//! Then, the response of the browser is passed to
+//! [`finish_registration()`] or [`login_by_passkey()`].
+//!
+//! The session data must implement [`PasskeySessionData`]
+//! to hold the challenge in between these steps.
+//!
+//! Only credentials using ES256 are supported,
+//! which is supported by all common authenticators.
+//! Attestation statements are not verified.
+
+use std::time::{Duration, SystemTime};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ciborium::Value;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AuditEvent, Authenticated, Backend, ComposedBackend, ComposedError,
+    Session, SessionStore, User, UserStore,
+};
+
+/// The number of random bytes in a challenge.
+const CHALLENGE_BYTES: usize = 32;
+
+/// The COSE algorithm identifier of ECDSA with P-256 and SHA-256.
+const COSE_ALG_ES256: i128 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The configuration of the relying party, ie. this application.
+#[derive(Clone, Debug)]
+pub struct PasskeyConfig {
+    /// The relying party id; the domain of the application,
+    /// such as `example.com`.
+    pub rp_id: String,
+    /// The human-readable name of the application.
+    pub rp_name: String,
+    /// The origin of the application, such as `https://example.com`.
+    pub origin: String,
+    /// Whether to require user verification, such as a PIN or biometrics.
+    pub require_user_verification: bool,
+    /// How long the user has to complete a ceremony.
+    pub timeout: Duration,
+}
+
+impl PasskeyConfig {
+    /// Create a new configuration.
+    ///
+    /// User verification is required, and ceremonies time out after 5 minutes.
+    pub fn new(rp_id: String, rp_name: String, origin: String) -> Self {
+        Self {
+            rp_id,
+            rp_name,
+            origin,
+            require_user_verification: true,
+            timeout: Duration::from_secs(5 * 60),
+        }
+    }
+
+    fn user_verification(&self) -> &'static str {
+        if self.require_user_verification {
+            "required"
+        } else {
+            "preferred"
+        }
+    }
+}
+
+/// The state of an ongoing ceremony, kept in the session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PasskeyChallenge {
+    challenge: Vec<u8>,
+    ceremony: Ceremony,
+    expires_at: SystemTime,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl PasskeyChallenge {
+    fn new(ceremony: Ceremony, config: &PasskeyConfig) -> Self {
+        let mut challenge = vec![0; CHALLENGE_BYTES];
+        rand::thread_rng().fill_bytes(&mut challenge);
+        Self {
+            challenge,
+            ceremony,
+            expires_at: SystemTime::now() + config.timeout,
+        }
+    }
+}
+
+/// The interface for session data that can hold
+/// the state of an ongoing passkey ceremony.
+pub trait PasskeySessionData {
+    /// Get the challenge of the ongoing ceremony, if any.
+    fn passkey_challenge(&mut self) -> &mut Option<PasskeyChallenge>;
+}
+
+/// A passkey as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct PasskeyCredential<UserId> {
+    /// The credential id, chosen by the authenticator.
+    pub id: Vec<u8>,
+    /// The user the passkey belongs to.
+    pub user_id: UserId,
+    /// The public key, as an uncompressed SEC1-encoded P-256 point.
+    pub public_key: Vec<u8>,
+    /// The signature counter last reported by the authenticator.
+    pub sign_count: u32,
+    /// When the passkey was registered.
+    pub created_at: SystemTime,
+    /// When the passkey was last used to authenticate.
+    pub last_used_at: Option<SystemTime>,
+}
+
+/// The interface for a backend that stores passkeys.
+pub trait PasskeyBackend: Backend {
+    /// Store a newly registered passkey.
+    fn store_passkey(
+        &self,
+        credential: &PasskeyCredential<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a passkey by its credential id.
+    fn load_passkey(
+        &self,
+        credential_id: &[u8],
+    ) -> future!(Output = Result<Option<PasskeyCredential<<Self::User as User>::Id>>, Error>);
+
+    /// Load all passkeys of a user.
+    fn list_passkeys(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<PasskeyCredential<<Self::User as User>::Id>>, Error>);
+
+    /// Update the signature counter of a passkey after it was used.
+    fn update_passkey_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that stores passkeys.
+///
+/// See [`PasskeyBackend`].
+pub trait PasskeyStore: UserStore {
+    /// Store a newly registered passkey.
+    fn store_passkey(
+        &self,
+        credential: &PasskeyCredential<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a passkey by its credential id.
+    fn load_passkey(
+        &self,
+        credential_id: &[u8],
+    ) -> future!(Output = Result<Option<PasskeyCredential<<Self::User as User>::Id>>, Error>);
+
+    /// Load all passkeys of a user.
+    fn list_passkeys(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<PasskeyCredential<<Self::User as User>::Id>>, Error>);
+
+    /// Update the signature counter of a passkey after it was used.
+    fn update_passkey_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> PasskeyBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: PasskeyStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn store_passkey(
+        &self,
+        credential: &PasskeyCredential<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_passkey(credential);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_passkey(
+        &self,
+        credential_id: &[u8],
+    ) -> future!(Output = Result<Option<PasskeyCredential<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.load_passkey(credential_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn list_passkeys(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<Vec<PasskeyCredential<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.list_passkeys(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn update_passkey_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+        last_used_at: SystemTime,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.update_passkey_sign_count(
+            credential_id,
+            sign_count,
+            last_used_at,
+        );
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// The response of `navigator.credentials.create()`, as JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegistrationResponse {
+    /// The credential id, encoded as base64url.
+    pub id: String,
+    /// The response of the authenticator.
+    pub response: AttestationResponse,
+}
+
+/// The response of an authenticator to a registration ceremony.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttestationResponse {
+    /// The client data, encoded as base64url.
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    /// The attestation object, encoded as base64url.
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// The response of `navigator.credentials.get()`, as JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuthenticationResponse {
+    /// The credential id, encoded as base64url.
+    pub id: String,
+    /// The response of the authenticator.
+    pub response: AssertionResponse,
+}
+
+/// The response of an authenticator to an authentication ceremony.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AssertionResponse {
+    /// The client data, encoded as base64url.
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    /// The authenticator data, encoded as base64url.
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    /// The signature, encoded as base64url.
+    pub signature: String,
+    /// The user handle, encoded as base64url.
+    #[serde(rename = "userHandle", default)]
+    pub user_handle: Option<String>,
+}
+
+/// The reason a passkey could not be registered or used.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PasskeyError {
+    /// No user is logged into the session.
+    NotAuthenticated,
+    /// A user is being [impersonated](Session::impersonate) in the session.
+    Impersonating,
+    /// The user logged into the session did not authenticate recently,
+    /// see [`Session::require_recent_auth()`].
+    ReauthenticationRequired,
+    /// The credential is unknown,
+    /// or the user it belongs to does not exist (anymore) or is not active.
+    UnknownCredential,
+    /// There is no ongoing ceremony, or it has expired.
+    NoChallenge,
+    /// The response could not be decoded.
+    Malformed,
+    /// The response is for a different ceremony.
+    ChallengeMismatch,
+    /// The response is for a different origin.
+    OriginMismatch,
+    /// The response is for a different relying party.
+    RelyingPartyMismatch,
+    /// The authenticator did not confirm the user was present.
+    UserNotPresent,
+    /// The authenticator did not verify the user.
+    UserNotVerified,
+    /// The credential uses an unsupported algorithm.
+    UnsupportedAlgorithm,
+    /// The credential was already registered.
+    AlreadyRegistered,
+    /// The signature counter did not increase,
+    /// so the authenticator may have been cloned.
+    CloneDetected,
+}
+
+impl std::fmt::Display for PasskeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotAuthenticated => "not authenticated",
+            Self::Impersonating => "impersonating a user",
+            Self::ReauthenticationRequired => "reauthentication required",
+            Self::UnknownCredential => "unknown credential",
+            Self::NoChallenge => "no ongoing ceremony",
+            Self::Malformed => "malformed response",
+            Self::ChallengeMismatch => "challenge mismatch",
+            Self::OriginMismatch => "origin mismatch",
+            Self::RelyingPartyMismatch => "relying party mismatch",
+            Self::UserNotPresent => "user not present",
+            Self::UserNotVerified => "user not verified",
+            Self::UnsupportedAlgorithm => "unsupported algorithm",
+            Self::AlreadyRegistered => "credential already registered",
+            Self::CloneDetected => "authenticator may have been cloned",
+        })
+    }
+}
+
+impl std::error::Error for PasskeyError {}
+
+fn decode(value: &str) -> Result<Vec<u8>, PasskeyError> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| PasskeyError::Malformed)
+}
+
+fn take_challenge<B>(
+    session: &mut Session<B>,
+    ceremony: Ceremony,
+) -> Result<Vec<u8>, PasskeyError>
+where
+    B: Backend,
+    B::SessionData: PasskeySessionData,
+{
+    let challenge = session.data.passkey_challenge().take();
+    session.needs_save();
+    match challenge {
+        Some(challenge)
+            if challenge.ceremony == ceremony
+                && SystemTime::now() < challenge.expires_at =>
+        {
+            Ok(challenge.challenge)
+        }
+        _ => Err(PasskeyError::NoChallenge),
+    }
+}
+
+/// The client data collected by the browser.
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+fn verify_client_data(
+    config: &PasskeyConfig,
+    client_data_json: &[u8],
+    kind: &str,
+    challenge: &[u8],
+) -> Result<(), PasskeyError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| PasskeyError::Malformed)?;
+    if client_data.kind != kind || decode(&client_data.challenge)? != challenge
+    {
+        return Err(PasskeyError::ChallengeMismatch);
+    }
+    if client_data.origin != config.origin {
+        return Err(PasskeyError::OriginMismatch);
+    }
+    Ok(())
+}
+
+/// The data produced by the authenticator.
+struct AuthenticatorData {
+    sign_count: u32,
+    /// The credential id and public key, when registering.
+    credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AuthenticatorData {
+    fn parse(
+        config: &PasskeyConfig,
+        data: &[u8],
+    ) -> Result<Self, PasskeyError> {
+        if data.len() < 37 {
+            return Err(PasskeyError::Malformed);
+        }
+        let rp_id_hash = Sha256::digest(config.rp_id.as_bytes());
+        if data[..32] != rp_id_hash[..] {
+            return Err(PasskeyError::RelyingPartyMismatch);
+        }
+        let flags = data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            return Err(PasskeyError::UserNotPresent);
+        }
+        if config.require_user_verification && flags & FLAG_USER_VERIFIED == 0 {
+            return Err(PasskeyError::UserNotVerified);
+        }
+        let sign_count = u32::from_be_bytes(data[33..37].try_into().unwrap());
+        let credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+            // Skip the AAGUID of the authenticator.
+            let data = data.get(53..).ok_or(PasskeyError::Malformed)?;
+            let (length, data) = data
+                .split_first_chunk::<2>()
+                .ok_or(PasskeyError::Malformed)?;
+            let length = u16::from_be_bytes(*length) as usize;
+            if data.len() < length {
+                return Err(PasskeyError::Malformed);
+            }
+            let (id, data) = data.split_at(length);
+            let public_key = parse_cose_key(data)?;
+            Some((id.to_vec(), public_key))
+        } else {
+            None
+        };
+        Ok(Self {
+            sign_count,
+            credential,
+        })
+    }
+}
+
+/// Parse a COSE public key into an uncompressed SEC1-encoded P-256 point.
+fn parse_cose_key(data: &[u8]) -> Result<Vec<u8>, PasskeyError> {
+    let key: Value =
+        ciborium::from_reader(data).map_err(|_| PasskeyError::Malformed)?;
+    let Value::Map(entries) = key else {
+        return Err(PasskeyError::Malformed);
+    };
+    let get = |label: i128| {
+        entries.iter().find_map(|(key, value)| match key {
+            Value::Integer(key) if i128::from(*key) == label => Some(value),
+            _ => None,
+        })
+    };
+    let integer = |label: i128| match get(label) {
+        Some(Value::Integer(value)) => Some(i128::from(*value)),
+        _ => None,
+    };
+    let bytes = |label: i128| match get(label) {
+        Some(Value::Bytes(value)) if value.len() == 32 => Some(value),
+        _ => None,
+    };
+    // The key type must be EC2, on curve P-256.
+    if integer(3) != Some(COSE_ALG_ES256)
+        || integer(1) != Some(2)
+        || integer(-1) != Some(1)
+    {
+        return Err(PasskeyError::UnsupportedAlgorithm);
+    }
+    let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+        return Err(PasskeyError::Malformed);
+    };
+    let mut public_key = Vec::with_capacity(65);
+    public_key.push(0x04);
+    public_key.extend_from_slice(x);
+    public_key.extend_from_slice(y);
+    VerifyingKey::from_sec1_bytes(&public_key)
+        .map_err(|_| PasskeyError::Malformed)?;
+    Ok(public_key)
+}
+
+/// Check that the user logged into the session may register a passkey.
+///
+/// Since a passkey grants lasting access to the account,
+/// the user must have authenticated within the last `max_age`,
+/// and must not be [impersonated](Session::impersonate).
+async fn check_registration<B: Backend>(
+    session: &mut Session<B>,
+    max_age: Duration,
+) -> Result<Result<(), PasskeyError>, B::Error> {
+    if session.impersonator().is_some() {
+        return Ok(Err(PasskeyError::Impersonating));
+    }
+    if session.user_mut().await?.is_none() {
+        return Ok(Err(PasskeyError::NotAuthenticated));
+    }
+    Ok(session
+        .require_recent_auth(max_age)
+        .await?
+        .map_err(|_| PasskeyError::ReauthenticationRequired))
+}
+
+/// Start registering a passkey for the user logged into the session.
+///
+/// This returns the options to pass to `navigator.credentials.create()`.
+/// The user must have authenticated within the last `max_age`,
+/// see [`Session::require_recent_auth()`],
+/// and this fails while [impersonating](Session::impersonate) a user.
+pub async fn start_registration<B>(
+    session: &mut Session<B>,
+    config: &PasskeyConfig,
+    max_age: Duration,
+) -> Result<Result<serde_json::Value, PasskeyError>, B::Error>
+where
+    B: PasskeyBackend,
+    B::SessionData: PasskeySessionData,
+    <B::User as User>::Id: std::fmt::Display,
+{
+    if let Err(e) = check_registration(session, max_age).await? {
+        return Ok(Err(e));
+    }
+    let Some(user) = session.user().await? else {
+        return Ok(Err(PasskeyError::NotAuthenticated));
+    };
+    let user_id = user.id().clone();
+    let email = user.email().to_owned();
+    let existing = session.backend.list_passkeys(&user_id).await?;
+    let challenge = PasskeyChallenge::new(Ceremony::Registration, config);
+    let options = serde_json::json!({
+        "challenge": URL_SAFE_NO_PAD.encode(&challenge.challenge),
+        "rp": {
+            "id": config.rp_id,
+            "name": config.rp_name,
+        },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(user_id.to_string()),
+            "name": email,
+            "displayName": email,
+        },
+        "pubKeyCredParams": [
+            {"type": "public-key", "alg": COSE_ALG_ES256},
+        ],
+        "timeout": config.timeout.as_millis() as u64,
+        "excludeCredentials": existing
+            .iter()
+            .map(|credential| serde_json::json!({
+                "type": "public-key",
+                "id": URL_SAFE_NO_PAD.encode(&credential.id),
+            }))
+            .collect::<Vec<_>>(),
+        "authenticatorSelection": {
+            "residentKey": "required",
+            "requireResidentKey": true,
+            "userVerification": config.user_verification(),
+        },
+        "attestation": "none",
+    });
+    *session.data.passkey_challenge() = Some(challenge);
+    session.needs_save();
+    Ok(Ok(options))
+}
+
+/// Finish registering a passkey for the user logged into the session.
+///
+/// On success, the passkey is stored by the backend and returned.
+/// Like [`start_registration()`], this requires that the user
+/// authenticated within the last `max_age`,
+/// and fails while [impersonating](Session::impersonate) a user.
+pub async fn finish_registration<B>(
+    session: &mut Session<B>,
+    config: &PasskeyConfig,
+    response: &RegistrationResponse,
+    max_age: Duration,
+) -> Result<
+    Result<PasskeyCredential<<B::User as User>::Id>, PasskeyError>,
+    B::Error,
+>
+where
+    B: PasskeyBackend,
+    B::SessionData: PasskeySessionData,
+{
+    let challenge = match take_challenge(session, Ceremony::Registration) {
+        Ok(challenge) => challenge,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Err(e) = check_registration(session, max_age).await? {
+        return Ok(Err(e));
+    }
+    let Some(user) = session.user().await? else {
+        return Ok(Err(PasskeyError::NotAuthenticated));
+    };
+    let user_id = user.id().clone();
+    let credential = match verify_registration(config, response, &challenge) {
+        Ok((id, public_key, sign_count)) => PasskeyCredential {
+            id,
+            user_id,
+            public_key,
+            sign_count,
+            created_at: SystemTime::now(),
+            last_used_at: None,
+        },
+        Err(e) => return Ok(Err(e)),
+    };
+    if session
+        .backend
+        .load_passkey(&credential.id)
+        .await?
+        .is_some()
+    {
+        return Ok(Err(PasskeyError::AlreadyRegistered));
+    }
+    session.backend.store_passkey(&credential).await?;
+    Ok(Ok(credential))
+}
+
+fn verify_registration(
+    config: &PasskeyConfig,
+    response: &RegistrationResponse,
+    challenge: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>, u32), PasskeyError> {
+    let client_data_json = decode(&response.response.client_data_json)?;
+    verify_client_data(
+        config,
+        &client_data_json,
+        "webauthn.create",
+        challenge,
+    )?;
+    let attestation_object = decode(&response.response.attestation_object)?;
+    let attestation: Value = ciborium::from_reader(&attestation_object[..])
+        .map_err(|_| PasskeyError::Malformed)?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|entries| {
+            entries.iter().find_map(|(key, value)| match (key, value) {
+                (Value::Text(key), Value::Bytes(value))
+                    if key == "authData" =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            })
+        })
+        .ok_or(PasskeyError::Malformed)?;
+    let auth_data = AuthenticatorData::parse(config, auth_data)?;
+    let Some((id, public_key)) = auth_data.credential else {
+        return Err(PasskeyError::Malformed);
+    };
+    if decode(&response.id)? != id {
+        return Err(PasskeyError::Malformed);
+    }
+    Ok((id, public_key, auth_data.sign_count))
+}
+
+/// Start logging into the session by passkey.
+///
+/// This returns the options to pass to `navigator.credentials.get()`.
+/// Since passkeys are discoverable, the user does not need to be known yet.
+pub fn start_login<B>(
+    session: &mut Session<B>,
+    config: &PasskeyConfig,
+) -> serde_json::Value
+where
+    B: Backend,
+    B::SessionData: PasskeySessionData,
+{
+    let challenge = PasskeyChallenge::new(Ceremony::Authentication, config);
+    let options = serde_json::json!({
+        "challenge": URL_SAFE_NO_PAD.encode(&challenge.challenge),
+        "rpId": config.rp_id,
+        "timeout": config.timeout.as_millis() as u64,
+        "allowCredentials": [],
+        "userVerification": config.user_verification(),
+    });
+    *session.data.passkey_challenge() = Some(challenge);
+    session.needs_save();
+    options
+}
+
+/// Try to log a user into the session by passkey.
+///
+/// This is the passkey equivalent of
+/// [`Session::login_by_password()`](crate::Session::login_by_password);
+/// if the response cannot be verified, this returns why,
+/// and the existing user (if any) remains logged in.
+///
+/// If the signature counter of the passkey did not increase,
+/// the authenticator may have been cloned.
+/// In that case, this returns [`PasskeyError::CloneDetected`]
+/// and records [`AuditEvent::PasskeyCloneDetected`].
+pub async fn login_by_passkey<B>(
+    session: &mut Session<B>,
+    config: &PasskeyConfig,
+    response: &AuthenticationResponse,
+) -> Result<Result<Authenticated, PasskeyError>, B::Error>
+where
+    B: PasskeyBackend,
+    B::SessionData: PasskeySessionData,
+    <B::User as User>::Id: std::fmt::Display,
+{
+    let challenge = match take_challenge(session, Ceremony::Authentication) {
+        Ok(challenge) => challenge,
+        Err(e) => return Ok(Err(e)),
+    };
+    let credential_id = match decode(&response.id) {
+        Ok(credential_id) => credential_id,
+        Err(e) => return Ok(Err(e)),
+    };
+    let Some(credential) = session.backend.load_passkey(&credential_id).await?
+    else {
+        return Ok(Err(PasskeyError::UnknownCredential));
+    };
+    let sign_count =
+        match verify_assertion(config, response, &challenge, &credential) {
+            Ok(sign_count) => sign_count,
+            Err(PasskeyError::CloneDetected) => {
+                session
+                    .backend
+                    .record_audit_event(&AuditEvent::PasskeyCloneDetected {
+                        user: credential.user_id,
+                        credential_id: credential.id,
+                    })
+                    .await?;
+                return Ok(Err(PasskeyError::CloneDetected));
+            }
+            Err(e) => return Ok(Err(e)),
+        };
+    let Some(user) = session.backend.load_user(&credential.user_id).await?
+    else {
+        return Ok(Err(PasskeyError::UnknownCredential));
+    };
+    if !user.account_status().is_active() {
+        return Ok(Err(PasskeyError::UnknownCredential));
+    }
+    session
+        .backend
+        .update_passkey_sign_count(
+            &credential.id,
+            sign_count,
+            SystemTime::now(),
+        )
+        .await?;
+    session.set_user(Some(user));
+    Ok(Ok(session.set_authenticated(Authenticated::new())))
+}
+
+fn verify_assertion<UserId: std::fmt::Display>(
+    config: &PasskeyConfig,
+    response: &AuthenticationResponse,
+    challenge: &[u8],
+    credential: &PasskeyCredential<UserId>,
+) -> Result<u32, PasskeyError> {
+    let response = &response.response;
+    let client_data_json = decode(&response.client_data_json)?;
+    verify_client_data(config, &client_data_json, "webauthn.get", challenge)?;
+    if let Some(user_handle) = &response.user_handle
+        && decode(user_handle)? != credential.user_id.to_string().as_bytes()
+    {
+        return Err(PasskeyError::Malformed);
+    }
+    let auth_data_bytes = decode(&response.authenticator_data)?;
+    let auth_data = AuthenticatorData::parse(config, &auth_data_bytes)?;
+    let key = VerifyingKey::from_sec1_bytes(&credential.public_key)
+        .map_err(|_| PasskeyError::Malformed)?;
+    let signature = Signature::from_der(&decode(&response.signature)?)
+        .map_err(|_| PasskeyError::Malformed)?;
+    let mut message = auth_data_bytes;
+    message.extend_from_slice(&Sha256::digest(&client_data_json));
+    key.verify(&message, &signature)
+        .map_err(|_| PasskeyError::Malformed)?;
+    // NOTE: Authenticators that do not count signatures always report zero.
+    // Otherwise, the counter must increase with every use;
+    // if it does not, the authenticator may have been cloned.
+    if (auth_data.sign_count != 0 || credential.sign_count != 0)
+        && auth_data.sign_count <= credential.sign_count
+    {
+        return Err(PasskeyError::CloneDetected);
+    }
+    Ok(auth_data.sign_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::SigningKey;
+    use p256::ecdsa::signature::Signer;
+
+    use super::*;
+    use crate::AccountStatus;
+    use crate::testing::TestBackend;
+
+    const CREDENTIAL_ID: &[u8] = b"credential";
+
+    const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+    fn config() -> PasskeyConfig {
+        PasskeyConfig::new(
+            "example.com".to_owned(),
+            "Example".to_owned(),
+            "https://example.com".to_owned(),
+        )
+    }
+
+    fn to_cbor(value: &Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).unwrap();
+        bytes
+    }
+
+    /// An authenticator implemented in software.
+    struct Authenticator {
+        key: SigningKey,
+        rp_id: String,
+        origin: String,
+        flags: u8,
+        sign_count: u32,
+    }
+
+    impl Authenticator {
+        fn new() -> Self {
+            Self {
+                key: SigningKey::from_slice(&[7; 32]).unwrap(),
+                rp_id: "example.com".to_owned(),
+                origin: "https://example.com".to_owned(),
+                flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+                sign_count: 0,
+            }
+        }
+
+        fn client_data(
+            &self,
+            kind: &str,
+            options: &serde_json::Value,
+        ) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({
+                "type": kind,
+                "challenge": options["challenge"],
+                "origin": self.origin,
+            }))
+            .unwrap()
+        }
+
+        fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        fn register(
+            &self,
+            options: &serde_json::Value,
+        ) -> RegistrationResponse {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let public_key = Value::Map(vec![
+                (1.into(), 2.into()),
+                (3.into(), (-7).into()),
+                ((-1).into(), 1.into()),
+                ((-2).into(), Value::Bytes(point.x().unwrap().to_vec())),
+                ((-3).into(), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            let mut auth_data = self
+                .authenticator_data(self.flags | FLAG_ATTESTED_CREDENTIAL_DATA);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data
+                .extend_from_slice(&(CREDENTIAL_ID.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(CREDENTIAL_ID);
+            auth_data.extend_from_slice(&to_cbor(&public_key));
+            let attestation = Value::Map(vec![
+                ("fmt".into(), "none".into()),
+                ("attStmt".into(), Value::Map(Vec::new())),
+                ("authData".into(), Value::Bytes(auth_data)),
+            ]);
+            RegistrationResponse {
+                id: URL_SAFE_NO_PAD.encode(CREDENTIAL_ID),
+                response: AttestationResponse {
+                    client_data_json: URL_SAFE_NO_PAD
+                        .encode(self.client_data("webauthn.create", options)),
+                    attestation_object: URL_SAFE_NO_PAD
+                        .encode(to_cbor(&attestation)),
+                },
+            }
+        }
+
+        fn authenticate(
+            &mut self,
+            options: &serde_json::Value,
+            user_id: u64,
+        ) -> AuthenticationResponse {
+            self.sign_count += 1;
+            let client_data = self.client_data("webauthn.get", options);
+            let auth_data = self.authenticator_data(self.flags);
+            let mut message = auth_data.clone();
+            message.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: Signature = self.key.sign(&message);
+            AuthenticationResponse {
+                id: URL_SAFE_NO_PAD.encode(CREDENTIAL_ID),
+                response: AssertionResponse {
+                    client_data_json: URL_SAFE_NO_PAD.encode(client_data),
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    signature: URL_SAFE_NO_PAD
+                        .encode(signature.to_der().as_bytes()),
+                    user_handle: Some(
+                        URL_SAFE_NO_PAD.encode(user_id.to_string()),
+                    ),
+                },
+            }
+        }
+    }
+
+    async fn register(
+        backend: &TestBackend,
+        authenticator: &Authenticator,
+    ) -> Result<PasskeyCredential<u64>, PasskeyError> {
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        let options = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap()
+            .unwrap();
+        let response = authenticator.register(&options);
+        finish_registration(&mut session, &config(), &response, MAX_AGE)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn register_and_login() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let credential = register(&backend, &authenticator).await.unwrap();
+        assert_eq!(credential.id, CREDENTIAL_ID);
+        assert_eq!(backend.state().passkeys.len(), 1);
+
+        let mut session = backend.session(None);
+        let options = start_login(&mut session, &config());
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert!(auth.is_ok());
+        assert_eq!(session.user.id(), Some(&credential.user_id));
+        assert_eq!(backend.state().passkeys[0].sign_count, 1);
+
+        // The challenge can only be used once.
+        let mut session = backend.session(None);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert_eq!(auth.unwrap_err(), PasskeyError::NoChallenge);
+    }
+
+    #[tokio::test]
+    async fn register_wrong_origin() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        authenticator.origin = "https://evil.example".to_owned();
+        let result = register(&backend, &authenticator).await;
+        assert_eq!(result.unwrap_err(), PasskeyError::OriginMismatch);
+    }
+
+    #[tokio::test]
+    async fn register_wrong_relying_party() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        authenticator.rp_id = "evil.example".to_owned();
+        let result = register(&backend, &authenticator).await;
+        assert_eq!(result.unwrap_err(), PasskeyError::RelyingPartyMismatch);
+    }
+
+    #[tokio::test]
+    async fn register_user_not_verified() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        authenticator.flags = FLAG_USER_PRESENT;
+        let result = register(&backend, &authenticator).await;
+        assert_eq!(result.unwrap_err(), PasskeyError::UserNotVerified);
+    }
+
+    #[tokio::test]
+    async fn register_while_impersonating() {
+        let backend = TestBackend::default();
+        let authenticator = Authenticator::new();
+        let user_id = backend.add_user("a@example.com");
+        let admin_id = backend.add_user("admin@example.com");
+        let mut session = backend.session(Some(user_id));
+        let options = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap()
+            .unwrap();
+        session.set_impersonator(Some(admin_id));
+        let response = authenticator.register(&options);
+        let result =
+            finish_registration(&mut session, &config(), &response, MAX_AGE)
+                .await
+                .unwrap();
+        assert_eq!(result.unwrap_err(), PasskeyError::Impersonating);
+        assert!(backend.state().passkeys.is_empty());
+
+        let result = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap_err(), PasskeyError::Impersonating);
+    }
+
+    #[tokio::test]
+    async fn register_requires_recent_auth() {
+        let backend = TestBackend::default();
+        let authenticator = Authenticator::new();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        let options = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap()
+            .unwrap();
+        let response = authenticator.register(&options);
+        let challenge = session.data.passkey_challenge().clone();
+
+        let mut session = backend.session_authenticated_at(
+            Some(user_id),
+            Some(SystemTime::now() - 2 * MAX_AGE),
+        );
+        let result = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap_err(), PasskeyError::ReauthenticationRequired);
+
+        *session.data.passkey_challenge() = challenge;
+        let result =
+            finish_registration(&mut session, &config(), &response, MAX_AGE)
+                .await
+                .unwrap();
+        assert_eq!(result.unwrap_err(), PasskeyError::ReauthenticationRequired);
+        assert!(backend.state().passkeys.is_empty());
+
+        let mut session = backend.session(None);
+        let result = start_registration(&mut session, &config(), MAX_AGE)
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap_err(), PasskeyError::NotAuthenticated);
+    }
+
+    #[tokio::test]
+    async fn login_wrong_challenge() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let credential = register(&backend, &authenticator).await.unwrap();
+        let mut session = backend.session(None);
+        start_login(&mut session, &config());
+        let options = serde_json::json!({
+            "challenge": URL_SAFE_NO_PAD.encode([0; CHALLENGE_BYTES]),
+        });
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert_eq!(auth.unwrap_err(), PasskeyError::ChallengeMismatch);
+        assert!(!session.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn login_bad_signature() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let credential = register(&backend, &authenticator).await.unwrap();
+        let mut session = backend.session(None);
+        let options = start_login(&mut session, &config());
+        authenticator.key = SigningKey::from_slice(&[8; 32]).unwrap();
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert_eq!(auth.unwrap_err(), PasskeyError::Malformed);
+        assert!(!session.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn login_inactive_user() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let credential = register(&backend, &authenticator).await.unwrap();
+        backend.state().users[0].status = AccountStatus::Disabled;
+        let mut session = backend.session(None);
+        let options = start_login(&mut session, &config());
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert_eq!(auth.unwrap_err(), PasskeyError::UnknownCredential);
+        assert!(!session.is_authenticated());
+    }
+
+    #[tokio::test]
+    async fn login_clone_detected() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let credential = register(&backend, &authenticator).await.unwrap();
+        backend.state().passkeys[0].sign_count = 5;
+        authenticator.sign_count = 4;
+        let mut session = backend.session(None);
+        let options = start_login(&mut session, &config());
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let auth = login_by_passkey(&mut session, &config(), &response)
+            .await
+            .unwrap();
+        assert_eq!(auth.unwrap_err(), PasskeyError::CloneDetected);
+        assert!(!session.is_authenticated());
+        assert_eq!(backend.state().passkeys[0].sign_count, 5);
+        assert!(matches!(
+            backend.state().audit_events.as_slice(),
+            [AuditEvent::PasskeyCloneDetected { user, credential_id }]
+                if *user == credential.user_id
+                    && credential_id == CREDENTIAL_ID
+        ));
+    }
+
+    #[tokio::test]
+    async fn sign_count_regression() {
+        let backend = TestBackend::default();
+        let mut authenticator = Authenticator::new();
+        let mut credential = register(&backend, &authenticator).await.unwrap();
+        credential.sign_count = 5;
+        let challenge = [1; CHALLENGE_BYTES];
+        let options = serde_json::json!({
+            "challenge": URL_SAFE_NO_PAD.encode(challenge),
+        });
+        authenticator.sign_count = 4;
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let result =
+            verify_assertion(&config(), &response, &challenge, &credential);
+        assert_eq!(result, Err(PasskeyError::CloneDetected));
+
+        authenticator.sign_count = 5;
+        let response = authenticator.authenticate(&options, credential.user_id);
+        let result =
+            verify_assertion(&config(), &response, &challenge, &credential);
+        assert_eq!(result, Ok(6));
+    }
+}
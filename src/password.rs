@@ -1,12 +1,19 @@
-/// The minimum length of a password to be considered valid.
+use unicode_normalization::UnicodeNormalization;
+
+/// The default minimum length of a password, in characters.
 pub const MIN_PASSWORD_LENGTH: usize = 8;
 
-/// The maximum length of a password to be considered valid.
+/// The default maximum length of a password, in characters.
 pub const MAX_PASSWORD_LENGTH: usize = 1024;
 
-/// A value from `[0-4]`, as per [`zxcvbn::Entropy::score()`].
-#[cfg(feature = "zxcvbn")]
-pub const MIN_PASSWORD_SCORE: zxcvbn::Score = zxcvbn::Score::Three;
+/// The default minimum strength of a password,
+/// as per [`PasswordStrength::score`].
+#[cfg(feature = "password-strength")]
+pub const MIN_PASSWORD_SCORE: u8 = 3;
+
+/// The policy used when the backend does not configure one.
+pub(crate) static DEFAULT_PASSWORD_POLICY: PasswordPolicy =
+    PasswordPolicy::new();
 
 macro_rules! define_algorithms {
     ($algorith0_type:ty: $algorithm0:expr, $($algorithm:expr,)*) => {
@@ -64,51 +71,335 @@ fn generate_salt() -> password_hash::SaltString {
     password_hash::SaltString::generate(&mut rng)
 }
 
+/// Normalize a password using NFKC, as recommended by NIST SP 800-63B.
+fn normalize(password: &str) -> String {
+    password.nfkc().collect()
+}
+
 /// A compile-time token to prove authentication.
 #[derive(Debug)]
 pub struct Authenticated(());
 
+impl Authenticated {
+    /// Create a proof of authentication.
+    ///
+    /// This must only be called after successfully verifying a credential.
+    pub(crate) fn new() -> Self {
+        Self(())
+    }
+}
+
+/// The rules a password must satisfy to be considered valid.
+///
+/// Following NIST SP 800-63B, passwords are normalized using NFKC
+/// and their length is counted in characters.
+/// Character class rules are disabled by default,
+/// since they tend to make passwords harder to remember
+/// without making them much harder to guess.
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    /// The minimum length, in characters.
+    pub min_length: usize,
+    /// The maximum length, in characters.
+    pub max_length: usize,
+    /// The minimum strength, as per [`PasswordStrength::score`].
+    #[cfg(feature = "password-strength")]
+    pub min_score: u8,
+    /// Passwords that are not allowed, compared case-insensitively.
+    ///
+    /// Use this for context-specific words, such as the name of the service.
+    pub denylist: Vec<String>,
+    /// The character classes a password must contain.
+    pub required_character_classes: Vec<CharacterClass>,
+    /// The number of most recent passwords of a user,
+    /// including the current one, a new password must not match.
+    ///
+    /// Each of these is verified when the password is updated,
+    /// so large values make updating passwords slow.
+    /// This defaults to `0`, which disables the check.
+    pub history_depth: usize,
+    /// The check of new passwords against compromised passwords.
+    ///
+    /// If the source of compromised passwords fails,
+    /// the password is accepted,
+    /// so that an unavailable source does not prevent users
+    /// from registering or changing their password.
+    /// This defaults to `None`, which disables the check.
+    #[cfg(feature = "breached-passwords")]
+    pub breach_check: Option<crate::BreachCheck>,
+}
+
+impl PasswordPolicy {
+    /// Create the default policy.
+    pub const fn new() -> Self {
+        Self {
+            min_length: MIN_PASSWORD_LENGTH,
+            max_length: MAX_PASSWORD_LENGTH,
+            #[cfg(feature = "password-strength")]
+            min_score: MIN_PASSWORD_SCORE,
+            denylist: Vec::new(),
+            required_character_classes: Vec::new(),
+            history_depth: 0,
+            #[cfg(feature = "breached-passwords")]
+            breach_check: None,
+        }
+    }
+
+    /// Check a normalized password against this policy.
+    fn check(
+        &self,
+        password: &str,
+        fields: &[&str],
+    ) -> Result<(), BadPassword> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(BadPassword::TooShort {
+                min_length: self.min_length,
+            });
+        }
+        if length > self.max_length {
+            return Err(BadPassword::TooLong {
+                max_length: self.max_length,
+            });
+        }
+        let lowercase = password.to_lowercase();
+        if self
+            .denylist
+            .iter()
+            .any(|denied| normalize(denied).to_lowercase() == lowercase)
+        {
+            return Err(BadPassword::Denylisted);
+        }
+        for &class in &self.required_character_classes {
+            if !password.chars().any(|c| class.contains(c)) {
+                return Err(BadPassword::MissingCharacterClass(class));
+            }
+        }
+        #[cfg(feature = "password-strength")]
+        {
+            let strength = PasswordStrength::estimate(password, fields);
+            if strength.score < self.min_score {
+                return Err(BadPassword::Weak(strength));
+            }
+        }
+        #[cfg(not(feature = "password-strength"))]
+        let _ = fields; // Avoid unused variable warning.
+        Ok(())
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A class of characters a password can be required to contain.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CharacterClass {
+    /// Lowercase letters.
+    Lowercase,
+    /// Uppercase letters.
+    Uppercase,
+    /// Digits.
+    Digit,
+    /// Any character that is not a letter or digit.
+    Symbol,
+}
+
+impl CharacterClass {
+    fn contains(self, c: char) -> bool {
+        match self {
+            Self::Lowercase => c.is_lowercase(),
+            Self::Uppercase => c.is_uppercase(),
+            Self::Digit => c.is_numeric(),
+            Self::Symbol => !c.is_alphanumeric(),
+        }
+    }
+}
+
+/// An estimate of the strength of a password.
+///
+/// This can be serialized to show feedback to the user,
+/// such as in a password strength meter.
+#[cfg(feature = "password-strength")]
+#[derive(Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PasswordStrength {
+    /// The strength, from 0 (too guessable) to 4 (very unguessable).
+    pub score: u8,
+    /// A warning explaining what makes the password weak, if any.
+    pub warning: Option<String>,
+    /// Suggestions to make the password stronger.
+    pub suggestions: Vec<String>,
+    /// The estimated time to crack the password, in seconds,
+    /// in an offline attack against a slow hash.
+    pub crack_time_seconds: u64,
+    /// The estimated time to crack the password, for display,
+    /// such as "3 hours" or "centuries".
+    pub crack_time_display: String,
+}
+
+#[cfg(feature = "password-strength")]
+impl PasswordStrength {
+    /// Estimate the strength of a password.
+    ///
+    /// The password is normalized using NFKC.
+    /// `fields` are user inputs, such as the email address,
+    /// that make a password weak when it contains them.
+    pub fn new(password: &str, fields: &[&str]) -> Self {
+        Self::estimate(&normalize(password), fields)
+    }
+
+    /// Estimate the strength of a normalized password.
+    fn estimate(password: &str, fields: &[&str]) -> Self {
+        let entropy = zxcvbn::zxcvbn(password, fields);
+        let crack_time =
+            entropy.crack_times().offline_slow_hashing_1e4_per_second();
+        let feedback = entropy.feedback();
+        Self {
+            score: entropy.score().into(),
+            warning: feedback
+                .and_then(|feedback| feedback.warning())
+                .map(|warning| warning.to_string()),
+            suggestions: feedback
+                .map(|feedback| feedback.suggestions())
+                .unwrap_or_default()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            crack_time_seconds: std::time::Duration::from(crack_time).as_secs(),
+            crack_time_display: crack_time.to_string(),
+        }
+    }
+}
+
 /// The reason a password is considered invalid.
 #[derive(Clone, Debug)]
 pub enum BadPassword {
     /// The password is too short.
-    TooShort,
+    TooShort {
+        /// The minimum length, in characters.
+        min_length: usize,
+    },
     /// The password is too long.
-    TooLong,
+    TooLong {
+        /// The maximum length, in characters.
+        max_length: usize,
+    },
+    /// The password is on the denylist.
+    Denylisted,
+    /// The password does not contain a required class of characters.
+    MissingCharacterClass(CharacterClass),
     /// The password is too weak.
-    #[cfg(feature = "zxcvbn")]
-    Weak(zxcvbn::Entropy),
+    #[cfg(feature = "password-strength")]
+    Weak(PasswordStrength),
+    /// The password appears in a data breach.
+    #[cfg(feature = "breached-passwords")]
+    Breached,
+    /// The password was used recently,
+    /// see [`PasswordPolicy::history_depth`].
+    Reused,
+}
+
+impl std::fmt::Display for BadPassword {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooShort { min_length } => {
+                write!(f, "password must be at least {min_length} characters")
+            }
+            Self::TooLong { max_length } => {
+                write!(f, "password must be at most {max_length} characters")
+            }
+            Self::Denylisted => f.write_str("password is not allowed"),
+            Self::MissingCharacterClass(class) => {
+                let class = match class {
+                    CharacterClass::Lowercase => "a lowercase letter",
+                    CharacterClass::Uppercase => "an uppercase letter",
+                    CharacterClass::Digit => "a digit",
+                    CharacterClass::Symbol => "a symbol",
+                };
+                write!(f, "password must contain {class}")
+            }
+            #[cfg(feature = "password-strength")]
+            Self::Weak(_) => f.write_str("password is too weak"),
+            #[cfg(feature = "breached-passwords")]
+            Self::Breached => f.write_str("password appears in a data breach"),
+            Self::Reused => f.write_str("password was used recently"),
+        }
+    }
+}
+
+impl std::error::Error for BadPassword {}
+
+/// The reason a password could not be changed.
+#[derive(Clone, Debug)]
+pub enum ChangePasswordError {
+    /// No user is logged into the session.
+    NotAuthenticated,
+    /// The current password is incorrect.
+    IncorrectPassword,
+    /// The new password is the same as the current password.
+    SamePassword,
+    /// The new password is invalid.
+    BadPassword(BadPassword),
+    /// A user is being [impersonated](crate::Session::impersonate).
+    Impersonating,
+}
+
+impl std::fmt::Display for ChangePasswordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAuthenticated => f.write_str("not authenticated"),
+            Self::IncorrectPassword => f.write_str("incorrect password"),
+            Self::SamePassword => {
+                f.write_str("new password is the same as the current password")
+            }
+            Self::BadPassword(e) => e.fmt(f),
+            Self::Impersonating => f.write_str("impersonating a user"),
+        }
+    }
+}
+
+impl std::error::Error for ChangePasswordError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::BadPassword(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 /// A password that has been validated.
 pub struct ValidPassword(String);
 
 impl ValidPassword {
-    /// Validate a password.
+    /// Validate a password against a policy.
+    ///
+    /// The password is normalized using NFKC.
+    /// `fields` are user inputs, such as the email address,
+    /// that make a password weak when it contains them.
+    /// If the policy has a
+    /// [`breach_check`](PasswordPolicy::breach_check),
+    /// passwords that appear in a data breach are rejected.
     pub async fn new(
         password: String,
+        policy: &PasswordPolicy,
         fields: &[&str],
     ) -> Result<Self, BadPassword> {
-        if password.len() < MIN_PASSWORD_LENGTH {
-            return Err(BadPassword::TooShort);
-        }
-        if password.len() > MAX_PASSWORD_LENGTH {
-            return Err(BadPassword::TooLong);
-        }
-        #[cfg(feature = "zxcvbn")]
+        let password = normalize(&password);
+        policy.check(&password, fields)?;
+        #[cfg(feature = "breached-passwords")]
+        if let Some(breach_check) = &policy.breach_check
+            && breach_check.is_breached(&password).await
         {
-            let entropy = zxcvbn::zxcvbn(&password, fields);
-            if entropy.score() < MIN_PASSWORD_SCORE {
-                return Err(BadPassword::Weak(entropy));
-            }
+            return Err(BadPassword::Breached);
         }
-        #[cfg(not(feature = "zxcvbn"))]
-        let _ = fields; // Avoid unused variable warning.
         Ok(Self(password))
     }
 }
 
 /// A password that has been hashed.
+#[derive(Clone)]
 pub struct HashedPassword(password_hash::PasswordHashString);
 
 impl HashedPassword {
@@ -127,16 +418,36 @@ impl HashedPassword {
     ///
     /// This functions returns a compile-time token to prove authentication.
     /// If the password is invalid, this returns `None`.
+    ///
+    /// The password is normalized using NFKC before it is verified.
+    /// Hashes created before passwords were normalized
+    /// also accept the password as it was entered,
+    /// so that existing users can still log in.
+    /// Such hashes are replaced by a hash of the normalized password
+    /// when the user changes their password.
     pub fn verify(&self, password: &str) -> Option<Authenticated> {
+        let normalized = normalize(password);
+        let candidates = if normalized == password {
+            &[normalized.as_str()][..]
+        } else {
+            &[normalized.as_str(), password][..]
+        };
         let hash = self.0.password_hash();
-        for algo in algorithms_verify() {
-            if algo.verify_password(password.as_ref(), &hash).is_ok() {
-                return Some(Authenticated(()));
+        for candidate in candidates {
+            for algo in algorithms_verify() {
+                if algo.verify_password(candidate.as_ref(), &hash).is_ok() {
+                    return Some(Authenticated::new());
+                }
             }
         }
         None
     }
 
+    /// Whether a valid password matches this hashed password.
+    pub(crate) fn matches(&self, password: &ValidPassword) -> bool {
+        self.verify(&password.0).is_some()
+    }
+
     /// Get the hashed password as a string.
     ///
     /// Be careful not to leak this value in logs or other places.
@@ -164,3 +475,140 @@ impl std::fmt::Debug for HashedPassword {
         f.write_str("HashedPassword([...])")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            #[cfg(feature = "password-strength")]
+            min_score: 0,
+            ..PasswordPolicy::new()
+        }
+    }
+
+    async fn check(
+        policy: &PasswordPolicy,
+        password: &str,
+    ) -> Result<String, BadPassword> {
+        let password = ValidPassword::new(password.to_owned(), policy, &[]);
+        password.await.map(|password| password.0)
+    }
+
+    #[tokio::test]
+    async fn normalize_nfkc() {
+        let policy = policy();
+        let cases = [
+            // The ligature "ﬁ" normalizes to "fi".
+            ("\u{fb01}ngerprint", "fingerprint"),
+            // Fullwidth letters normalize to ASCII.
+            ("\u{ff50}\u{ff41}\u{ff53}\u{ff53}word1", "password1"),
+            // Combining accents are composed.
+            ("cafe\u{301} au lait", "caf\u{e9} au lait"),
+            ("correct horse", "correct horse"),
+        ];
+        for (password, normalized) in cases {
+            assert_eq!(check(&policy, password).await.unwrap(), normalized);
+        }
+    }
+
+    #[tokio::test]
+    async fn length_in_characters() {
+        let policy = PasswordPolicy {
+            min_length: 4,
+            max_length: 6,
+            ..policy()
+        };
+        let cases = [
+            ("abc", false),
+            ("abcd", true),
+            // 4 characters in 8 bytes.
+            ("\u{e9}\u{e9}\u{e9}\u{e9}", true),
+            // 6 characters in 18 bytes.
+            ("\u{20ac}\u{20ac}\u{20ac}\u{20ac}\u{20ac}\u{20ac}", true),
+            ("abcdefg", false),
+            // 8 characters before normalization, 4 after.
+            ("e\u{301}e\u{301}e\u{301}e\u{301}", true),
+        ];
+        for (password, valid) in cases {
+            let result = check(&policy, password).await;
+            assert_eq!(result.is_ok(), valid, "{password:?}");
+        }
+        assert!(matches!(
+            check(&policy, "abc").await,
+            Err(BadPassword::TooShort { min_length: 4 }),
+        ));
+        assert!(matches!(
+            check(&policy, "abcdefg").await,
+            Err(BadPassword::TooLong { max_length: 6 }),
+        ));
+    }
+
+    #[tokio::test]
+    async fn denylist() {
+        let policy = PasswordPolicy {
+            denylist: vec!["Autho Service".to_owned()],
+            ..policy()
+        };
+        for password in ["autho service", "AUTHO SERVICE"] {
+            assert!(matches!(
+                check(&policy, password).await,
+                Err(BadPassword::Denylisted),
+            ));
+        }
+        assert!(check(&policy, "autho service 2").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn character_classes() {
+        let policy = PasswordPolicy {
+            required_character_classes: vec![
+                CharacterClass::Lowercase,
+                CharacterClass::Uppercase,
+                CharacterClass::Digit,
+                CharacterClass::Symbol,
+            ],
+            ..policy()
+        };
+        let cases = [
+            ("CORRECT HORSE 1", CharacterClass::Lowercase),
+            ("correct horse 1", CharacterClass::Uppercase),
+            ("Correct horse !", CharacterClass::Digit),
+            ("CorrectHorse1", CharacterClass::Symbol),
+        ];
+        for (password, missing) in cases {
+            assert!(matches!(
+                check(&policy, password).await,
+                Err(BadPassword::MissingCharacterClass(class))
+                    if class == missing,
+            ));
+        }
+        assert!(check(&policy, "Correct horse 1").await.is_ok());
+        // Non-ASCII letters and digits count too.
+        assert!(
+            check(&policy, "\u{c9}cole \u{e9}t\u{e9} \u{663}")
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_normalized() {
+        let password = check(&policy(), "\u{fb01}ngerprint").await.unwrap();
+        let hashed = HashedPassword::new(&ValidPassword(password));
+        assert!(hashed.verify("\u{fb01}ngerprint").is_some());
+        assert!(hashed.verify("fingerprint").is_some());
+        assert!(hashed.verify("fingerprint!").is_none());
+    }
+
+    #[test]
+    fn verify_unnormalized_hash() {
+        // A hash created before passwords were normalized.
+        let password = ValidPassword("\u{fb01}ngerprint".to_owned());
+        let hashed = HashedPassword::new(&password);
+        assert!(hashed.verify("\u{fb01}ngerprint").is_some());
+        // The normalized password was never hashed.
+        assert!(hashed.verify("fingerprint").is_none());
+    }
+}
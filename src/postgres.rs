@@ -3,7 +3,7 @@ use std::error::Error;
 use bytes::BytesMut;
 use postgres_types::{FromSql, IsNull, ToSql, Type};
 
-use crate::HashedPassword;
+use crate::{HashedPassword, SessionId};
 
 impl ToSql for HashedPassword {
     fn to_sql(
@@ -47,3 +47,45 @@ impl<'a> FromSql<'a> for HashedPassword {
         <String as FromSql>::accepts(ty)
     }
 }
+
+impl ToSql for SessionId {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>>
+    where
+        Self: Sized,
+    {
+        <&[u8] as ToSql>::to_sql(&&self.0[..], ty, out)
+    }
+
+    fn to_sql_checked(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        <&[u8] as ToSql>::to_sql_checked(&&self.0[..], ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool
+    where
+        Self: Sized,
+    {
+        <&[u8] as ToSql>::accepts(ty)
+    }
+}
+
+impl<'a> FromSql<'a> for SessionId {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let bytes = <&[u8] as FromSql>::from_sql(ty, raw)?;
+        Ok(Self(bytes.try_into()?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&[u8] as FromSql>::accepts(ty)
+    }
+}
@@ -0,0 +1,595 @@
+use std::time::{Duration, SystemTime};
+
+use crate::token_utils::{random_hex, sha256};
+use crate::{
+    Authenticated, Backend, ComposedBackend, ComposedError, Session,
+    SessionStore, User, UserSessionsBackend, UserStore,
+};
+
+/// The number of random bytes in the selector of a remember-me token.
+const SELECTOR_BYTES: usize = 16;
+
+/// The number of random bytes in the validator of a remember-me token.
+const VALIDATOR_BYTES: usize = 32;
+
+/// A long-lived token to log in again after the session expired,
+/// typically stored in a cookie.
+///
+/// The token consists of a selector, used to look it up,
+/// and a validator, which is never stored by the backend, only its hash.
+/// The validator is replaced every time the token is used.
+pub struct RememberMeToken(String);
+
+impl RememberMeToken {
+    /// Generate a new random token.
+    fn generate() -> Self {
+        Self::rotate(&random_hex(SELECTOR_BYTES))
+    }
+
+    /// Generate a new random validator for a selector.
+    fn rotate(selector: &str) -> Self {
+        Self(format!("{selector}.{}", random_hex(VALIDATOR_BYTES)))
+    }
+
+    /// Get the token as a string.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the selector of this token.
+    pub fn selector(&self) -> &str {
+        self.parts().0
+    }
+
+    /// Get the hash of the validator of this token,
+    /// as stored by the backend.
+    pub fn hash(&self) -> RememberMeHash {
+        RememberMeHash::new(self.parts().1)
+    }
+
+    fn parts(&self) -> (&str, &str) {
+        self.0.split_once('.').unwrap_or((&self.0, ""))
+    }
+}
+
+impl std::fmt::Debug for RememberMeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RememberMeToken([...])")
+    }
+}
+
+impl std::str::FromStr for RememberMeToken {
+    type Err = InvalidRememberMeToken;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('.') {
+            Some((selector, validator))
+                if !selector.is_empty() && !validator.is_empty() =>
+            {
+                Ok(Self(s.to_owned()))
+            }
+            _ => Err(InvalidRememberMeToken),
+        }
+    }
+}
+
+/// The error returned when parsing an invalid [`RememberMeToken`].
+#[derive(Copy, Clone, Debug)]
+pub struct InvalidRememberMeToken;
+
+impl std::fmt::Display for InvalidRememberMeToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid remember-me token")
+    }
+}
+
+impl std::error::Error for InvalidRememberMeToken {}
+
+/// The SHA-256 hash of the validator of a [`RememberMeToken`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RememberMeHash(pub [u8; 32]);
+
+impl RememberMeHash {
+    fn new(validator: &str) -> Self {
+        Self(sha256(validator))
+    }
+}
+
+/// A remember-me token as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct RememberMeRecord<UserId> {
+    /// The selector of the token.
+    pub selector: String,
+    /// The hash of the current validator of the token.
+    pub hash: RememberMeHash,
+    /// The user the token logs in as.
+    pub user_id: UserId,
+    /// When the token expires.
+    pub expires_at: SystemTime,
+    /// The hash of the validator replaced by the current one,
+    /// and until when it is still accepted, if any.
+    ///
+    /// See [`RememberMeBackend::remember_me_grace_period()`].
+    pub previous: Option<(RememberMeHash, SystemTime)>,
+}
+
+/// The interface for a backend that supports remember-me tokens.
+pub trait RememberMeBackend: Backend {
+    /// Get how long remember-me tokens remain valid after they were used.
+    fn remember_me_ttl(&self) -> Duration {
+        Duration::from_secs(30 * 24 * 60 * 60)
+    }
+
+    /// Get how long the previous validator of a remember-me token
+    /// is still accepted after the token was used.
+    ///
+    /// Clients often send several requests at the same time,
+    /// all with the same token, of which only the first one
+    /// replaces the validator.
+    /// Without this grace period,
+    /// the others would look like the use of a stolen token.
+    fn remember_me_grace_period(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Get the name of the remember-me cookie.
+    fn remember_me_cookie_name(&self) -> &str {
+        "rememberme"
+    }
+
+    /// Store a new remember-me token.
+    fn store_remember_me_token(
+        &self,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a remember-me token by its selector.
+    fn load_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<Option<RememberMeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Replace a remember-me token with one with the same selector.
+    ///
+    /// The token must only be replaced if its hash still equals `hash`,
+    /// in which case this returns `true`.
+    /// This must be atomic, so that each validator can only be used once.
+    fn replace_remember_me_token(
+        &self,
+        hash: &RememberMeHash,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete a remember-me token by its selector.
+    fn delete_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Delete all remember-me tokens of a user.
+    ///
+    /// See [`forget_all_remember_me_tokens()`].
+    fn delete_user_remember_me_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+/// The interface for a user store that supports remember-me tokens.
+///
+/// See [`RememberMeBackend`].
+pub trait RememberMeStore: UserStore {
+    /// Get how long remember-me tokens remain valid after they were used.
+    fn remember_me_ttl(&self) -> Duration {
+        Duration::from_secs(30 * 24 * 60 * 60)
+    }
+
+    /// Get how long the previous validator of a remember-me token
+    /// is still accepted after the token was used.
+    ///
+    /// See [`RememberMeBackend::remember_me_grace_period()`].
+    fn remember_me_grace_period(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Get the name of the remember-me cookie.
+    fn remember_me_cookie_name(&self) -> &str {
+        "rememberme"
+    }
+
+    /// Store a new remember-me token.
+    fn store_remember_me_token(
+        &self,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Load a remember-me token by its selector.
+    fn load_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<Option<RememberMeRecord<<Self::User as User>::Id>>, Error>);
+
+    /// Replace a remember-me token with one with the same selector.
+    ///
+    /// See [`RememberMeBackend::replace_remember_me_token()`].
+    fn replace_remember_me_token(
+        &self,
+        hash: &RememberMeHash,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<bool, Error>);
+
+    /// Delete a remember-me token by its selector.
+    fn delete_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<(), Error>);
+
+    /// Delete all remember-me tokens of a user.
+    fn delete_user_remember_me_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>);
+}
+
+impl<S, U> RememberMeBackend for ComposedBackend<S, U>
+where
+    S: SessionStore<UserId = <U::User as User>::Id>,
+    U: RememberMeStore,
+    S::Error: 'static,
+    U::Error: 'static,
+{
+    fn remember_me_ttl(&self) -> Duration {
+        self.users.remember_me_ttl()
+    }
+
+    fn remember_me_grace_period(&self) -> Duration {
+        self.users.remember_me_grace_period()
+    }
+
+    fn remember_me_cookie_name(&self) -> &str {
+        self.users.remember_me_cookie_name()
+    }
+
+    fn store_remember_me_token(
+        &self,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.store_remember_me_token(token);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn load_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<Option<RememberMeRecord<<Self::User as User>::Id>>, Error>)
+    {
+        let future = self.users.load_remember_me_token(selector);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn replace_remember_me_token(
+        &self,
+        hash: &RememberMeHash,
+        token: &RememberMeRecord<<Self::User as User>::Id>,
+    ) -> future!(Output = Result<bool, Error>) {
+        let future = self.users.replace_remember_me_token(hash, token);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn delete_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.delete_remember_me_token(selector);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+
+    fn delete_user_remember_me_tokens(
+        &self,
+        user_id: &<Self::User as User>::Id,
+    ) -> future!(Output = Result<(), Error>) {
+        let future = self.users.delete_user_remember_me_tokens(user_id);
+        async move { future.await.map_err(ComposedError::User) }
+    }
+}
+
+/// Issue a remember-me token for the user logged into the session.
+///
+/// Call this after the user logged in with "remember me" checked,
+/// for example using [`Session::login_by_password()`],
+/// which returns the proof of authentication this requires.
+/// The token should be sent to the client in a long-lived cookie.
+/// If no user is logged into the session,
+/// or while [impersonating](Session::impersonate) a user,
+/// this returns `None`.
+pub async fn remember_me<B: RememberMeBackend>(
+    session: &Session<B>,
+    auth: &Authenticated,
+) -> Result<Option<RememberMeToken>, B::Error> {
+    let _ = auth;
+    let Some(user_id) = session.user.id() else {
+        return Ok(None);
+    };
+    if session.impersonator().is_some() {
+        return Ok(None);
+    }
+    let token = RememberMeToken::generate();
+    let record = RememberMeRecord {
+        selector: token.selector().to_owned(),
+        hash: token.hash(),
+        user_id: user_id.clone(),
+        expires_at: SystemTime::now() + session.backend.remember_me_ttl(),
+        previous: None,
+    };
+    session.backend.store_remember_me_token(&record).await?;
+    Ok(Some(token))
+}
+
+/// The result of logging in by a remember-me token,
+/// see [`login_by_remember_me()`].
+#[derive(Debug)]
+pub enum RememberMeLogin {
+    /// The token was replaced by a new one,
+    /// which must be sent to the client.
+    Rotated(RememberMeToken),
+    /// The token was already replaced by a concurrent request,
+    /// which sends the new token to the client.
+    AlreadyRotated,
+}
+
+/// Try to log a user into the session by a remember-me token.
+///
+/// On success, this usually returns a new token replacing the one used,
+/// which must be sent to the client.
+/// The user is logged in without authenticating,
+/// so [`Session::require_recent_auth()`] still fails.
+///
+/// If the token is unknown or expired,
+/// or if the user it belongs to does not exist (anymore) or is not active,
+/// this returns `None` and the existing user (if any) remains logged in.
+/// If the selector is known but the validator does not match,
+/// the token was most likely stolen and used by someone else.
+/// In that case, all remember-me tokens and other sessions
+/// of the user are deleted and this returns `None`.
+/// The previous validator of a token is still accepted
+/// for a short time after it was replaced,
+/// see [`RememberMeBackend::remember_me_grace_period()`].
+pub async fn login_by_remember_me<B>(
+    session: &mut Session<B>,
+    token: &str,
+) -> Result<Option<RememberMeLogin>, B::Error>
+where
+    B: RememberMeBackend + UserSessionsBackend,
+{
+    let Ok(token) = token.parse::<RememberMeToken>() else {
+        return Ok(None);
+    };
+    let backend = &session.backend;
+    let Some(record) = backend.load_remember_me_token(token.selector()).await?
+    else {
+        return Ok(None);
+    };
+    let now = SystemTime::now();
+    if now >= record.expires_at {
+        backend.delete_remember_me_token(&record.selector).await?;
+        return Ok(None);
+    }
+    let hash = token.hash();
+    let current = record.hash == hash;
+    if !current
+        && !record
+            .previous
+            .is_some_and(|(previous, until)| previous == hash && now < until)
+    {
+        backend
+            .delete_user_remember_me_tokens(&record.user_id)
+            .await?;
+        backend
+            .delete_user_sessions(&record.user_id, session.id())
+            .await?;
+        return Ok(None);
+    }
+    let Some(user) = backend.load_user(&record.user_id).await? else {
+        return Ok(None);
+    };
+    if !user.account_status().is_active() {
+        return Ok(None);
+    }
+    let mut login = RememberMeLogin::AlreadyRotated;
+    if current {
+        let new_token = RememberMeToken::rotate(&record.selector);
+        let new_record = RememberMeRecord {
+            selector: record.selector,
+            hash: new_token.hash(),
+            user_id: record.user_id,
+            expires_at: now + backend.remember_me_ttl(),
+            previous: Some((
+                record.hash,
+                now + backend.remember_me_grace_period(),
+            )),
+        };
+        // NOTE: If the token was replaced concurrently,
+        // the validator is now the previous one, which is still accepted.
+        if backend
+            .replace_remember_me_token(&record.hash, &new_record)
+            .await?
+        {
+            login = RememberMeLogin::Rotated(new_token);
+        }
+    }
+    session.set_user(Some(user));
+    Ok(Some(login))
+}
+
+/// Delete a remember-me token, such as when the user logs out.
+///
+/// If the token is unknown or invalid, this does nothing.
+pub async fn forget_remember_me_token<B: RememberMeBackend>(
+    backend: &B,
+    token: &str,
+) -> Result<(), B::Error> {
+    let Ok(token) = token.parse::<RememberMeToken>() else {
+        return Ok(());
+    };
+    if let Some(record) =
+        backend.load_remember_me_token(token.selector()).await?
+        && record.hash == token.hash()
+    {
+        backend.delete_remember_me_token(&record.selector).await?;
+    }
+    Ok(())
+}
+
+/// Delete all remember-me tokens of the user logged into the session.
+///
+/// Call this when the credentials of the user may have been compromised,
+/// such as after [changing the password](Session::change_password)
+/// or [logging out other sessions](Session::logout_other_sessions),
+/// so that the other sessions cannot be restored by their tokens.
+/// The remember-me cookie of this session becomes invalid as well.
+/// If no user is logged into the session, this does nothing.
+pub async fn forget_all_remember_me_tokens<B: RememberMeBackend>(
+    session: &Session<B>,
+) -> Result<(), B::Error> {
+    match session.user.id() {
+        Some(user_id) => {
+            session
+                .backend
+                .delete_user_remember_me_tokens(user_id)
+                .await
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestBackend;
+    use crate::{PasswordPolicy, ValidPassword};
+
+    async fn remembered(backend: &TestBackend, user_id: u64) -> String {
+        let session = backend.session(Some(user_id));
+        let token = remember_me(&session, &Authenticated::new())
+            .await
+            .unwrap()
+            .unwrap();
+        token.as_str().to_owned()
+    }
+
+    async fn login(
+        backend: &TestBackend,
+        token: &str,
+    ) -> Option<RememberMeLogin> {
+        let mut session = backend.session(None);
+        let login = login_by_remember_me(&mut session, token).await.unwrap();
+        assert_eq!(login.is_some(), session.is_authenticated());
+        login
+    }
+
+    #[tokio::test]
+    async fn rotate() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let Some(RememberMeLogin::Rotated(new_token)) =
+            login(&backend, &token).await
+        else {
+            panic!("token not rotated");
+        };
+        assert_ne!(new_token.as_str(), token);
+        assert!(matches!(
+            login(&backend, new_token.as_str()).await,
+            Some(RememberMeLogin::Rotated(_)),
+        ));
+    }
+
+    #[tokio::test]
+    async fn concurrent_use() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let Some(RememberMeLogin::Rotated(new_token)) =
+            login(&backend, &token).await
+        else {
+            panic!("token not rotated");
+        };
+        // The previous validator is still accepted within the grace period.
+        assert!(matches!(
+            login(&backend, &token).await,
+            Some(RememberMeLogin::AlreadyRotated),
+        ));
+        assert!(matches!(
+            login(&backend, new_token.as_str()).await,
+            Some(RememberMeLogin::Rotated(_)),
+        ));
+    }
+
+    #[tokio::test]
+    async fn reuse_after_grace_period() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let other = remembered(&backend, user_id).await;
+        let Some(RememberMeLogin::Rotated(new_token)) =
+            login(&backend, &token).await
+        else {
+            panic!("token not rotated");
+        };
+        for record in &mut backend.state().remember_me_tokens {
+            if let Some((_, until)) = &mut record.previous {
+                *until = SystemTime::now();
+            }
+        }
+        assert!(login(&backend, &token).await.is_none());
+        assert!(login(&backend, new_token.as_str()).await.is_none());
+        assert!(login(&backend, &other).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stolen_token() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let mut other_session = backend.session(Some(user_id));
+        other_session.save().await.unwrap();
+        let (selector, _) = token.split_once('.').unwrap();
+        let stolen = format!("{selector}.{}", "0".repeat(64));
+        assert!(login(&backend, &stolen).await.is_none());
+        assert!(login(&backend, &token).await.is_none());
+        assert!(backend.state().sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn logout_other_sessions() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let session = backend.session(Some(user_id));
+        session.logout_other_sessions().await.unwrap().unwrap();
+        forget_all_remember_me_tokens(&session).await.unwrap();
+        assert!(login(&backend, &token).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn update_password() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let token = remembered(&backend, user_id).await;
+        let mut session = backend.session(Some(user_id));
+        let policy = PasswordPolicy::default();
+        let password =
+            ValidPassword::new("new passphrase".to_owned(), &policy, &[])
+                .await
+                .unwrap();
+        session
+            .update_user_password(&password)
+            .await
+            .unwrap()
+            .unwrap();
+        forget_all_remember_me_tokens(&session).await.unwrap();
+        assert!(login(&backend, &token).await.is_none());
+    }
+}
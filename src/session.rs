@@ -1,41 +1,323 @@
 use std::cell::Cell;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::token_utils::{random_hex, sha256};
 use crate::user::SessionUser;
-use crate::{Authenticated, Backend, User, ValidPassword};
+use crate::{
+    Authenticated, Backend, BadPassword, ChangePasswordError,
+    RegistrationBackend, User, UserSessionsBackend, ValidPassword,
+};
 
-/// A unique identifier to associate a user with a session.
+/// The minimum number of random bytes in a session token.
+pub const MIN_SESSION_TOKEN_BYTES: usize = 32;
+
+/// How often [`SessionMetadata::last_active_at`] is updated at most.
+const ACTIVITY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// A secret token to associate a user with a session.
 ///
 /// This value is intended to be shared with users to identify themselves.
+/// The token itself is never stored by the backend,
+/// only its hash, the [`SessionId`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    /// Generate a new random token of `bytes` random bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes` is less than [`MIN_SESSION_TOKEN_BYTES`].
+    pub fn generate(bytes: usize) -> Self {
+        assert!(
+            bytes >= MIN_SESSION_TOKEN_BYTES,
+            "session tokens must have at least {MIN_SESSION_TOKEN_BYTES} bytes",
+        );
+        Self(random_hex(bytes))
+    }
+
+    /// Get the token as a string.
+    ///
+    /// Be careful not to leak this value in logs or other places.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Get the id of the session, as stored by the backend.
+    pub fn id(&self) -> SessionId {
+        SessionId(sha256(&self.0))
+    }
+}
+
+impl std::fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SessionToken([...])")
+    }
+}
+
+/// The error returned when parsing an invalid [`SessionToken`].
+#[derive(Copy, Clone, Debug)]
+pub struct InvalidSessionToken;
+
+impl std::fmt::Display for InvalidSessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid session token")
+    }
+}
+
+impl std::error::Error for InvalidSessionToken {}
+
+impl std::str::FromStr for SessionToken {
+    type Err = InvalidSessionToken;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() >= 2 * MIN_SESSION_TOKEN_BYTES
+            && s.len().is_multiple_of(2)
+            && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            Ok(Self(s.to_owned()))
+        } else {
+            Err(InvalidSessionToken)
+        }
+    }
+}
+
+/// The SHA-256 hash of a [`SessionToken`],
+/// used by the backend to identify a session.
+///
+/// Unlike the token, this can be safely shown to the user,
+/// such as to revoke a session, see [`Session::revoke_session()`].
+/// It is formatted as hex.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
-#[cfg_attr(
-    feature = "postgres",
-    derive(postgres_types::ToSql, postgres_types::FromSql)
-)]
-pub struct SessionId(pub uuid::Uuid);
-
-impl SessionId {
-    /// Generate a new unique session id.
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
-        Self(uuid::Uuid::new_v4())
+pub struct SessionId(pub [u8; 32]);
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when parsing an invalid [`SessionId`].
+#[derive(Copy, Clone, Debug)]
+pub struct InvalidSessionId;
+
+impl std::fmt::Display for InvalidSessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid session id")
     }
 }
 
+impl std::error::Error for InvalidSessionId {}
+
 impl std::str::FromStr for SessionId {
-    type Err = uuid::Error;
+    type Err = InvalidSessionId;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(uuid::Uuid::parse_str(s)?))
+        let mut id = [0; 32];
+        if s.len() != 2 * id.len() || !s.is_ascii() {
+            return Err(InvalidSessionId);
+        }
+        for (byte, hex) in id.iter_mut().zip(s.as_bytes().chunks(2)) {
+            let hex = std::str::from_utf8(hex).map_err(|_| InvalidSessionId)?;
+            *byte =
+                u8::from_str_radix(hex, 16).map_err(|_| InvalidSessionId)?;
+        }
+        Ok(Self(id))
     }
 }
 
+/// Information about the client and activity of a session,
+/// for example to show the user a list of their active sessions.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SessionMetadata {
+    /// The user agent of the client that created the session,
+    /// or that the user last logged in with.
+    pub user_agent: Option<String>,
+    /// The IP address of the client that created the session,
+    /// or that the user last logged in with.
+    pub ip_address: Option<IpAddr>,
+    /// When the session was created.
+    pub created_at: SystemTime,
+    /// When the session was last used.
+    ///
+    /// This is updated when the session is saved,
+    /// and at most every five minutes when it is used,
+    /// see [`Session::touch()`].
+    pub last_active_at: SystemTime,
+}
+
+impl SessionMetadata {
+    /// Create the metadata of a session created now, by an unknown client.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let now = SystemTime::now();
+        Self {
+            user_agent: None,
+            ip_address: None,
+            created_at: now,
+            last_active_at: now,
+        }
+    }
+}
+
+/// A session as listed by [`UserSessionsBackend::list_user_sessions()`](crate::UserSessionsBackend::list_user_sessions).
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    /// The unique identifier of the session.
+    pub id: SessionId,
+    /// The metadata of the session.
+    pub metadata: SessionMetadata,
+}
+
 /// The fields associated with session as stored by the backend.
+#[derive(Clone, Debug)]
+pub struct SessionFields<UserId, Data> {
+    /// The user id associated with the session.
+    pub user_id: Option<UserId>,
+    /// Any implementation-defined data associated with the session.
+    pub data: Data,
+    /// The version of the stored session.
+    ///
+    /// This is incremented every time the session is updated,
+    /// and is `0` for a session that has not been stored yet.
+    pub version: u64,
+    /// When the user last authenticated in the session, if ever.
+    ///
+    /// This has a precision of seconds.
+    pub authenticated_at: Option<SystemTime>,
+    /// The user impersonating the user associated with the session, if any.
+    pub impersonator: Option<UserId>,
+    /// Information about the client and activity of the session.
+    pub metadata: SessionMetadata,
+}
+
+/// The fields of a session to store in the backend,
+/// see [`Backend::update_session_data()`].
 #[derive(Debug)]
-pub struct SessionFields<B: Backend> {
+pub struct SessionUpdate<'a, UserId, Data> {
+    /// The unique identifier of the session.
+    pub id: &'a SessionId,
+    /// The version of the session the update is based on.
+    ///
+    /// This is `0` for a session that has not been stored yet.
+    pub version: u64,
     /// The user id associated with the session.
-    pub user_id: Option<<B::User as User>::Id>,
+    pub user_id: Option<&'a UserId>,
+    /// When the user last authenticated in the session, if ever,
+    /// see [`Session::authenticated_at()`].
+    pub authenticated_at: Option<SystemTime>,
+    /// The user impersonating the user associated with the session, if any,
+    /// see [`Session::impersonator()`].
+    pub impersonator: Option<&'a UserId>,
+    /// Information about the client and activity of the session.
+    pub metadata: &'a SessionMetadata,
     /// Any implementation-defined data associated with the session.
-    pub data: B::SessionData,
+    pub data: &'a Data,
+}
+
+/// The error returned when saving a session fails.
+#[derive(Debug)]
+pub enum SaveError<E> {
+    /// The backend returned an error.
+    Backend(E),
+    /// The session was modified concurrently,
+    /// and the changes could not be merged.
+    Conflict,
+}
+
+impl<E> From<E> for SaveError<E> {
+    fn from(error: E) -> Self {
+        Self::Backend(error)
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for SaveError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Backend(e) => e.fmt(f),
+            Self::Conflict => f.write_str("session was modified concurrently"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for SaveError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend(e) => Some(e),
+            Self::Conflict => None,
+        }
+    }
+}
+
+/// The error returned when the user did not authenticate recently enough.
+///
+/// See [`Session::require_recent_auth()`].
+#[derive(Copy, Clone, Debug)]
+pub struct ReauthenticationRequired;
+
+impl std::fmt::Display for ReauthenticationRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("recent authentication required")
+    }
+}
+
+impl std::error::Error for ReauthenticationRequired {}
+
+/// The error returned when a sensitive action is attempted
+/// while [impersonating](Session::impersonate) a user.
+#[derive(Copy, Clone, Debug)]
+pub struct Impersonating;
+
+impl std::fmt::Display for Impersonating {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("not allowed while impersonating a user")
+    }
+}
+
+impl std::error::Error for Impersonating {}
+
+/// The reason impersonating a user failed.
+///
+/// See [`Session::impersonate()`].
+#[derive(Copy, Clone, Debug)]
+pub enum ImpersonationError {
+    /// No user is logged into the session.
+    NotAuthenticated,
+    /// The user logged into the session is already impersonating a user.
+    AlreadyImpersonating,
+    /// The user to impersonate does not exist.
+    UnknownUser,
+}
+
+impl std::fmt::Display for ImpersonationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotAuthenticated => "not authenticated",
+            Self::AlreadyImpersonating => "already impersonating a user",
+            Self::UnknownUser => "unknown user",
+        })
+    }
+}
+
+impl std::error::Error for ImpersonationError {}
+
+/// The authentication state of a session as stored in the backend:
+/// the user, when they authenticated and the impersonator.
+type StoredAuth<UserId> = (Option<UserId>, Option<SystemTime>, Option<UserId>);
+
+/// The current time, truncated to whole seconds.
+///
+/// Authentication times have a precision of seconds,
+/// so they are unchanged when stored by backends
+/// that store them as seconds since the Unix epoch.
+fn now_in_seconds() -> SystemTime {
+    let now = SystemTime::now();
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())
 }
 
 /// A user session.
@@ -44,86 +326,360 @@ pub struct Session<B: Backend> {
     pub backend: B,
     /// Any implementation-defined data associated with the session.
     pub data: B::SessionData,
-    /// The unique identifier for the session.
+    /// The secret token identifying the session.
+    token: SessionToken,
+    /// The unique identifier for the session, derived from the token.
     id: SessionId,
+    /// The id the session is stored under,
+    /// if its token was replaced since it was last saved.
+    previous_id: Option<SessionId>,
     /// The (optional) user associated with the session.
     pub(crate) user: SessionUser<B::User>,
+    /// The version of the session as last loaded from or stored in the backend.
+    version: u64,
+    /// When the user last authenticated in the session.
+    authenticated_at: Option<SystemTime>,
+    /// The user impersonating the user of the session.
+    impersonator: Option<<B::User as User>::Id>,
+    /// The user, authentication time and impersonator
+    /// as last loaded from or stored in the backend.
+    stored_auth: StoredAuth<<B::User as User>::Id>,
+    /// Information about the client and activity of the session.
+    metadata: SessionMetadata,
+    /// The user agent and IP address of the client of the current request.
+    client: (Option<String>, Option<IpAddr>),
     /// Whether the session needs to be saved in the backend because it contains changes.
     needs_save: Cell<bool>,
 }
 
 impl<B: Backend> Session<B> {
     /// Create a new session.
+    ///
+    /// For a session that has not been stored in the backend yet,
+    /// the version of the fields must be `0`.
     pub fn new(
         backend: B,
-        id: SessionId,
-        user_id: Option<<B::User as User>::Id>,
-        data: B::SessionData,
+        token: SessionToken,
+        fields: SessionFields<<B::User as User>::Id, B::SessionData>,
     ) -> Self {
         Self {
             backend,
-            id,
-            data,
-            user: SessionUser::new(user_id),
+            id: token.id(),
+            token,
+            previous_id: None,
+            data: fields.data,
+            stored_auth: (
+                fields.user_id.clone(),
+                fields.authenticated_at,
+                fields.impersonator.clone(),
+            ),
+            user: SessionUser::new(fields.user_id),
+            version: fields.version,
+            authenticated_at: fields.authenticated_at,
+            impersonator: fields.impersonator,
+            metadata: fields.metadata,
+            client: (None, None),
             needs_save: Cell::new(false),
         }
     }
 
+    /// Get the secret token identifying the session.
+    ///
+    /// This is the value to send to the client, such as in a cookie.
+    /// The token changes when the user of the session changes,
+    /// such as when a user logs in or out.
+    pub fn token(&self) -> &SessionToken {
+        &self.token
+    }
+
+    /// Get the unique identifier of the session.
+    pub fn id(&self) -> &SessionId {
+        &self.id
+    }
+
+    /// Get information about the client and activity of the session.
+    pub fn metadata(&self) -> &SessionMetadata {
+        &self.metadata
+    }
+
+    /// Record the client making the current request.
+    ///
+    /// The user agent and IP address are stored in the metadata
+    /// when the session is created or a user logs in.
+    pub fn set_client(
+        &mut self,
+        user_agent: Option<String>,
+        ip_address: Option<IpAddr>,
+    ) {
+        self.client = (user_agent, ip_address);
+        if !self.is_stored() {
+            self.update_client_metadata();
+        }
+    }
+
+    /// Store the current client in the metadata.
+    fn update_client_metadata(&mut self) {
+        (self.metadata.user_agent, self.metadata.ip_address) =
+            self.client.clone();
+    }
+
+    /// Whether the session has been stored in the backend.
+    ///
+    /// New sessions are kept in memory only,
+    /// until a user logs in or they are marked as needing to be saved,
+    /// so that anonymous visitors do not create sessions.
+    /// There is no need to send the session token to the client
+    /// for a session that has not been stored.
+    pub fn is_stored(&self) -> bool {
+        self.version > 0
+    }
+
     /// Whether the session is authenticated;
     /// ie. if there is a user logged into this session.
+    ///
+    /// Once the user was [loaded](Self::user),
+    /// this is `false` if they do not exist (anymore) or are not active.
     pub fn is_authenticated(&self) -> bool {
         self.user.is_authenticated()
     }
 
     /// Get the (optional) user logged into the session.
+    ///
+    /// If the user does not exist anymore,
+    /// or if their account is not [active](crate::AccountStatus::is_active),
+    /// this returns `None` as if no user was logged in.
     pub async fn user(&self) -> Result<Option<&B::User>, B::Error> {
         self.user.user(&self.backend).await
     }
 
     /// Get the (optional) user logged into the session.
     pub async fn user_mut(&mut self) -> Result<Option<&mut B::User>, B::Error> {
-        self.user.user_mut(&self.backend).await
+        // NOTE: Unlike `user()`, this does not hold a shared reference
+        // across the await, so the future is `Send`.
+        if let Some(id) = self.user.unloaded_id() {
+            let user = self.backend.load_user(id).await?;
+            self.user.set_loaded(user);
+        }
+        Ok(self.user.get_mut())
     }
 
     /// Change the user associated with the session.
+    ///
+    /// See [`change_user()`](Self::change_user).
     pub(crate) fn set_user_id(
         &mut self,
         user_id: Option<<B::User as User>::Id>,
     ) {
         if user_id.as_ref() != self.user.id() {
-            self.needs_save();
+            self.change_user(user_id.as_ref());
         }
         self.user.set_id(user_id);
     }
 
     /// Change the user associated with the session.
+    ///
+    /// See [`change_user()`](Self::change_user).
     pub(crate) fn set_user(&mut self, user: Option<B::User>) {
         if user.as_ref().map(|user| user.id()) != self.user.id() {
-            self.needs_save();
+            self.change_user(user.as_ref().map(|user| user.id()));
         }
         self.user.set_user(user);
     }
 
+    /// Prepare the session for a different user.
+    ///
+    /// The data of the session is updated using
+    /// [`Backend::carry_session_data()`],
+    /// and the session gets a new token,
+    /// so that a token known before the user changed,
+    /// such as one planted by an attacker (session fixation),
+    /// cannot be used afterwards.
+    /// The session is stored under the new id when it is saved,
+    /// and deleted under its previous id.
+    fn change_user(&mut self, user_id: Option<&<B::User as User>::Id>) {
+        self.backend.carry_session_data(
+            &mut self.data,
+            self.user.id(),
+            user_id,
+        );
+        self.authenticated_at = None;
+        self.impersonator = None;
+        self.update_client_metadata();
+        if self.is_stored() {
+            self.previous_id = Some(self.id);
+        }
+        let bytes =
+            (self.token.as_str().len() / 2).max(MIN_SESSION_TOKEN_BYTES);
+        self.token = SessionToken::generate(bytes);
+        self.id = self.token.id();
+        self.version = 0;
+        self.needs_save();
+    }
+
+    /// Get the user impersonating the user logged into the session, if any.
+    ///
+    /// See [`impersonate()`](Self::impersonate).
+    pub fn impersonator(&self) -> Option<&<B::User as User>::Id> {
+        self.impersonator.as_ref()
+    }
+
+    /// Record that a user is impersonating the user of the session.
+    pub(crate) fn set_impersonator(
+        &mut self,
+        impersonator: Option<<B::User as User>::Id>,
+    ) {
+        self.impersonator = impersonator;
+        self.needs_save();
+    }
+
+    /// Get when the user last authenticated in the session,
+    /// for example by entering their password.
+    ///
+    /// This is `None` if no user is logged in,
+    /// or if the user was logged in without authenticating,
+    /// such as through [`force_login()`](Self::force_login).
+    pub fn authenticated_at(&self) -> Option<SystemTime> {
+        self.authenticated_at
+    }
+
+    /// Record that the user logged into the session just authenticated.
+    pub(crate) fn set_authenticated(
+        &mut self,
+        auth: Authenticated,
+    ) -> Authenticated {
+        self.authenticated_at = Some(now_in_seconds());
+        self.needs_save();
+        auth
+    }
+
+    /// Require that the user logged into the session
+    /// authenticated within the last `max_age`.
+    ///
+    /// Use this to protect sensitive actions,
+    /// such as changing the email address or deleting the account.
+    /// If this fails, ask the user to re-enter their password
+    /// using [`reauthenticate()`](Self::reauthenticate).
+    ///
+    /// This always fails while [impersonating](Self::impersonate) a user,
+    /// and if the user does not exist (anymore) or is not active.
+    pub async fn require_recent_auth(
+        &mut self,
+        max_age: Duration,
+    ) -> Result<Result<(), ReauthenticationRequired>, B::Error> {
+        if self.user_mut().await?.is_none() {
+            return Ok(Err(ReauthenticationRequired));
+        }
+        Ok(match self.authenticated_at {
+            Some(authenticated_at)
+                if self.impersonator.is_none()
+                    && authenticated_at
+                        .elapsed()
+                        .is_ok_and(|elapsed| elapsed <= max_age) =>
+            {
+                Ok(())
+            }
+            _ => Err(ReauthenticationRequired),
+        })
+    }
+
     /// Mark this session as needing to be saved in the backend.
     pub fn needs_save(&self) {
         self.needs_save.set(true);
     }
 
     /// Save this session in the backend, if it has been marked as needing to be saved.
-    pub async fn save(&self) -> Result<(), B::Error> {
+    pub async fn save(&mut self) -> Result<(), SaveError<B::Error>> {
         if self.needs_save.get() {
             self.force_save().await?;
         }
         Ok(())
     }
 
+    /// Record that the session is being used.
+    ///
+    /// This saves the session if it was last active
+    /// more than five minutes ago,
+    /// to keep [`SessionMetadata::last_active_at`] up to date
+    /// without writing to the backend on every request.
+    /// Sessions that have not been stored yet are not saved.
+    pub async fn touch(&mut self) -> Result<(), B::Error> {
+        if !self.is_stored()
+            || self
+                .metadata
+                .last_active_at
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed < ACTIVITY_INTERVAL)
+        {
+            return Ok(());
+        }
+        match self.force_save().await {
+            // NOTE: On a conflict, the session was saved concurrently,
+            // which updated the activity as well.
+            Ok(()) | Err(SaveError::Conflict) => Ok(()),
+            Err(SaveError::Backend(e)) => Err(e),
+        }
+    }
+
     /// Save this session in the backend, even if it has not been marked as needing to be saved.
-    pub async fn force_save(&self) -> Result<(), B::Error> {
-        self.backend
-            .update_session_data(&self.id, self.user.id(), &self.data)
-            .await?;
-        self.needs_save.set(false);
-        Ok(())
+    ///
+    /// If the session was modified concurrently since it was loaded,
+    /// the data is merged using [`Backend::merge_session_data()`]
+    /// and saving is retried.
+    /// If the data cannot be merged,
+    /// or if the user, authentication time or impersonator
+    /// of the session changed concurrently,
+    /// this returns [`SaveError::Conflict`].
+    pub async fn force_save(&mut self) -> Result<(), SaveError<B::Error>> {
+        self.metadata.last_active_at = SystemTime::now();
+        loop {
+            let updated = self
+                .backend
+                .update_session_data(SessionUpdate {
+                    id: &self.id,
+                    version: self.version,
+                    user_id: self.user.id(),
+                    authenticated_at: self.authenticated_at,
+                    impersonator: self.impersonator.as_ref(),
+                    metadata: &self.metadata,
+                    data: &self.data,
+                })
+                .await?;
+            if updated {
+                self.version += 1;
+                self.stored_auth = (
+                    self.user.id().cloned(),
+                    self.authenticated_at,
+                    self.impersonator.clone(),
+                );
+                self.needs_save.set(false);
+                if let Some(previous_id) = self.previous_id.take() {
+                    self.backend.delete_session_data(&previous_id).await?;
+                }
+                return Ok(());
+            }
+            let Some(current) =
+                self.backend.load_session_data(&self.id).await?
+            else {
+                // The session was removed concurrently.
+                return Err(SaveError::Conflict);
+            };
+            // NOTE: Only the data is merged; a concurrent login, logout
+            // or reauthentication must not be silently overwritten.
+            let (user_id, authenticated_at, impersonator) = &self.stored_auth;
+            if current.user_id != *user_id
+                || current.impersonator != *impersonator
+                || current.authenticated_at != *authenticated_at
+            {
+                return Err(SaveError::Conflict);
+            }
+            let Some(data) =
+                self.backend.merge_session_data(&self.data, current.data)
+            else {
+                return Err(SaveError::Conflict);
+            };
+            self.data = data;
+            self.version = current.version;
+        }
     }
 
     /// Force a different user to be logged into the session.
@@ -146,16 +702,33 @@ impl<B: Backend> Session<B> {
 
     /// Try to log a user into session by password.
     ///
+    /// The user is identified by their email address,
+    /// or another identifier supported by the backend,
+    /// see [`authenticate_by_password()`](crate::authenticate_by_password).
+    ///
     /// If a user is currently logged into this session,
     /// this function tries to login the new user.
     /// If successful, the existing user is logged out.
     /// On failure, the existing user remains logged in.
     pub async fn login_by_password(
         &mut self,
-        email: &str,
+        login: &str,
         password: &str,
     ) -> Result<Option<Authenticated>, B::Error> {
-        crate::func::login_by_password(self, email, password).await
+        crate::func::login_by_password(self, login, password).await
+    }
+
+    /// Verify the password of the user logged into the session again,
+    /// for example before a sensitive action.
+    ///
+    /// See [`require_recent_auth()`](Self::require_recent_auth).
+    /// If no user is logged in or the password is incorrect,
+    /// this returns `None`.
+    pub async fn reauthenticate(
+        &mut self,
+        password: &str,
+    ) -> Result<Option<Authenticated>, B::Error> {
+        crate::func::reauthenticate(self, password).await
     }
 
     /// Logout the user of the session.
@@ -170,11 +743,224 @@ impl<B: Backend> Session<B> {
     /// Update the password of the user logged into the session.
     ///
     /// If no user is currently logged into this session,
+    /// or if the user does not exist (anymore) or is not active,
     /// this function does nothing.
+    ///
+    /// This does not verify the current password;
+    /// when the user changes their own password,
+    /// use [`change_password()`](Self::change_password) instead.
+    /// To also delete the remember-me tokens of the user,
+    /// use [`forget_all_remember_me_tokens()`](crate::forget_all_remember_me_tokens).
+    /// If the password matches a recent password of the user,
+    /// this returns [`BadPassword::Reused`],
+    /// see [`crate::PasswordPolicy::history_depth`].
+    /// While [impersonating](Self::impersonate) a user,
+    /// this returns [`ChangePasswordError::Impersonating`].
     pub async fn update_user_password(
         &mut self,
         password: &ValidPassword,
-    ) -> Result<(), B::Error> {
+    ) -> Result<Result<(), ChangePasswordError>, B::Error> {
         crate::func::update_user_password(self, password).await
     }
+
+    /// Impersonate another user, for example to help them as an admin.
+    ///
+    /// The user logged into the session becomes the impersonator,
+    /// see [`impersonator()`](Self::impersonator),
+    /// and sensitive actions are blocked,
+    /// see [`require_recent_auth()`](Self::require_recent_auth).
+    /// Use [`stop_impersonating()`](Self::stop_impersonating)
+    /// to log the impersonator back in.
+    /// This is recorded using [`Backend::record_audit_event()`].
+    ///
+    /// This does not check whether the user logged into the session
+    /// is allowed to impersonate other users;
+    /// this must be checked before calling this function.
+    pub async fn impersonate(
+        &mut self,
+        user_id: &<B::User as User>::Id,
+    ) -> Result<Result<(), ImpersonationError>, B::Error> {
+        crate::func::impersonate(self, user_id).await
+    }
+
+    /// Stop impersonating a user,
+    /// and log the impersonator back into the session.
+    ///
+    /// The impersonator is logged in without authenticating,
+    /// so [`require_recent_auth()`](Self::require_recent_auth) fails.
+    /// If no user is being impersonated, this returns `false`.
+    pub async fn stop_impersonating(&mut self) -> Result<bool, B::Error> {
+        crate::func::stop_impersonating(self).await
+    }
+}
+
+impl<B: RegistrationBackend> Session<B> {
+    /// Register a new user and log them into the session.
+    ///
+    /// If a user with this email address already exists, this returns `None`
+    /// and the existing user (if any) remains logged in.
+    /// See [`register()`](crate::register) for how to
+    /// prevent enumerating email addresses.
+    pub async fn register(
+        &mut self,
+        email: &str,
+        password: String,
+    ) -> Result<Result<Option<Authenticated>, BadPassword>, B::Error> {
+        crate::func::register_and_login(self, email, password).await
+    }
+}
+
+impl<B: UserSessionsBackend> Session<B> {
+    /// Change the password of the user logged into the session.
+    ///
+    /// This verifies the current password,
+    /// and validates the new password using the email address as a user input.
+    /// If `revoke_other_sessions` is set,
+    /// the user is logged out from all their other sessions,
+    /// see [`logout_other_sessions()`](Self::logout_other_sessions).
+    pub async fn change_password(
+        &mut self,
+        current: &str,
+        new: String,
+        revoke_other_sessions: bool,
+    ) -> Result<Result<(), ChangePasswordError>, B::Error> {
+        crate::func::change_password(self, current, new, revoke_other_sessions)
+            .await
+    }
+
+    /// Logout the user of the session from all their other sessions.
+    ///
+    /// To prevent the other sessions from being restored
+    /// by their remember-me tokens, use
+    /// [`forget_all_remember_me_tokens()`](crate::forget_all_remember_me_tokens).
+    /// If no user is currently logged into this session,
+    /// this function does nothing.
+    /// While [impersonating](Self::impersonate) a user, this fails.
+    pub async fn logout_other_sessions(
+        &self,
+    ) -> Result<Result<(), Impersonating>, B::Error> {
+        crate::func::logout_other_sessions(self).await
+    }
+
+    /// List the sessions of the user logged into the session,
+    /// including this session.
+    ///
+    /// If no user is currently logged into this session,
+    /// this returns an empty list.
+    pub async fn active_sessions(&self) -> Result<Vec<SessionInfo>, B::Error> {
+        match self.user.id() {
+            Some(user_id) => self.backend.list_user_sessions(user_id).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Revoke another session of the user logged into the session.
+    ///
+    /// If no user is currently logged into this session,
+    /// or if the session belongs to another user,
+    /// this function does nothing.
+    /// To end this session, use [`logout()`](Self::logout) instead.
+    pub async fn revoke_session(&self, id: &SessionId) -> Result<(), B::Error> {
+        match self.user.id() {
+            Some(user_id) if *id != self.id => {
+                self.backend.delete_user_session(user_id, id).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AccountStatus;
+    use crate::testing::TestBackend;
+
+    #[tokio::test]
+    async fn touch() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        session.touch().await.unwrap();
+        assert!(!session.is_stored());
+
+        session.force_save().await.unwrap();
+        let saved_at = session.metadata.last_active_at;
+        session.touch().await.unwrap();
+        assert_eq!(session.version, 1);
+
+        session.metadata.last_active_at = saved_at - ACTIVITY_INTERVAL;
+        session.touch().await.unwrap();
+        assert_eq!(session.version, 2);
+        let state = backend.state();
+        let stored = &state.sessions[session.id()].metadata;
+        assert!(stored.last_active_at >= saved_at);
+    }
+
+    #[tokio::test]
+    async fn require_recent_auth() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let max_age = Duration::from_secs(60);
+        let mut session = backend.session(Some(user_id));
+        let result = session.require_recent_auth(max_age).await.unwrap();
+        assert!(result.is_ok());
+
+        let mut session = backend.session_authenticated_at(
+            Some(user_id),
+            Some(SystemTime::now() - 2 * max_age),
+        );
+        let result = session.require_recent_auth(max_age).await.unwrap();
+        assert!(result.is_err());
+
+        let mut session = backend.session(None);
+        let result = session.require_recent_auth(max_age).await.unwrap();
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn require_recent_auth_inactive_user() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(Some(user_id));
+        assert!(session.is_authenticated());
+
+        backend.state().users[0].status = AccountStatus::Disabled;
+        let max_age = Duration::from_secs(60);
+        let result = session.require_recent_auth(max_age).await.unwrap();
+        assert!(result.is_err());
+        assert!(!session.is_authenticated());
+        assert!(session.user().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn new_token_on_login() {
+        let backend = TestBackend::default();
+        let user_id = backend.add_user("a@example.com");
+        let mut session = backend.session(None);
+        session.force_save().await.unwrap();
+        let anonymous_id = *session.id();
+
+        session.force_login(user_id).await.unwrap();
+        assert_ne!(*session.id(), anonymous_id);
+        assert!(!session.is_stored());
+        session.save().await.unwrap();
+        let logged_in_id = *session.id();
+        {
+            let state = backend.state();
+            assert!(!state.sessions.contains_key(&anonymous_id));
+            assert_eq!(state.sessions[&logged_in_id].user_id, Some(user_id));
+        }
+
+        // The token only changes with the user.
+        session.force_login(user_id).await.unwrap();
+        assert_eq!(*session.id(), logged_in_id);
+
+        session.logout().await.unwrap();
+        session.save().await.unwrap();
+        assert_ne!(*session.id(), logged_in_id);
+        let state = backend.state();
+        assert!(!state.sessions.contains_key(&logged_in_id));
+        assert_eq!(state.sessions.len(), 1);
+    }
 }
@@ -0,0 +1,1219 @@
+//! Storage backends using [`sqlx`].
+//!
+//! This module provides a [`SqlxSessionStore`] and a [`SqlxUserStore`]
+//! for SQLite, PostgreSQL and MySQL,
+//! each enabled through their respective `sqlx-*` feature.
+//! They can be combined into a [`Backend`](crate::Backend)
+//! through [`SqlxBackend`].
+//!
+//! The expected database schema is created by the bundled migrations,
+//! see [`SQLITE_MIGRATOR`], [`POSTGRES_MIGRATOR`] and [`MYSQL_MIGRATOR`].
+//! These migrators ignore migrations they do not know about,
+//! so they can be run against a database managed by other migrations.
+//!
+//! Session data is stored in a single column.
+//! Any type that can be encoded by `sqlx` can be used,
+//! such as [`sqlx::types::Json`].
+//! Users are loaded from the `autho_user` table
+//! through their [`FromRow`] implementation,
+//! so the table can be extended with additional columns.
+
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::{Database, Decode, Encode, FromRow, Pool, Type};
+
+use crate::{
+    ComposedBackend, EmailChangeRecord, EmailChangeTokenHash, HashedPassword,
+    SessionId, SessionInfo, SessionMetadata, User,
+};
+
+/// A session store backed by a `sqlx` connection pool.
+pub struct SqlxSessionStore<DB: Database, UserId, Data> {
+    pool: Pool<DB>,
+    marker: PhantomData<fn() -> (UserId, Data)>,
+}
+
+impl<DB: Database, UserId, Data> SqlxSessionStore<DB, UserId, Data> {
+    /// Create a new session store using a connection pool.
+    pub fn new(pool: Pool<DB>) -> Self {
+        Self {
+            pool,
+            marker: PhantomData,
+        }
+    }
+
+    /// Get the connection pool.
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+}
+
+impl<DB: Database, UserId, Data> Clone for SqlxSessionStore<DB, UserId, Data> {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+}
+
+/// A user store backed by a `sqlx` connection pool.
+pub struct SqlxUserStore<DB: Database, U> {
+    pool: Pool<DB>,
+    marker: PhantomData<fn() -> U>,
+}
+
+impl<DB: Database, U> SqlxUserStore<DB, U> {
+    /// Create a new user store using a connection pool.
+    pub fn new(pool: Pool<DB>) -> Self {
+        Self {
+            pool,
+            marker: PhantomData,
+        }
+    }
+
+    /// Get the connection pool.
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+}
+
+impl<DB: Database, U> Clone for SqlxUserStore<DB, U> {
+    fn clone(&self) -> Self {
+        Self::new(self.pool.clone())
+    }
+}
+
+/// A backend storing both sessions and users using `sqlx`.
+pub type SqlxBackend<DB, U, Data> = ComposedBackend<
+    SqlxSessionStore<DB, <U as User>::Id, Data>,
+    SqlxUserStore<DB, U>,
+>;
+
+impl<DB: Database, U: User, Data> SqlxBackend<DB, U, Data> {
+    /// Create a new backend using a connection pool for both stores.
+    pub fn from_pool(pool: Pool<DB>) -> Self {
+        ComposedBackend::new(
+            SqlxSessionStore::new(pool.clone()),
+            SqlxUserStore::new(pool),
+        )
+    }
+}
+
+/// The error type of the `sqlx` stores.
+#[derive(Debug)]
+pub struct Error(pub sqlx::Error);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(error: sqlx::Error) -> Self {
+        Self(error)
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    }
+}
+
+/// Convert a time to the number of seconds since the Unix epoch.
+fn to_timestamp(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Convert the number of seconds since the Unix epoch to a time.
+fn from_timestamp(timestamp: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64)
+}
+
+/// A session, as loaded from the database.
+type SessionRow<UserId, Data> = (
+    Option<UserId>,
+    Data,
+    i64,
+    Option<i64>,
+    Option<UserId>,
+    Option<String>,
+    Option<String>,
+    i64,
+    i64,
+);
+
+/// The session metadata columns, as loaded from the database.
+type MetadataRow = (Option<String>, Option<String>, i64, i64);
+
+/// A pending email change, as loaded from the database.
+type EmailChangeRow<UserId> = (
+    EmailChangeTokenHash,
+    EmailChangeTokenHash,
+    UserId,
+    String,
+    String,
+    i64,
+);
+
+/// Convert the email change columns to the email change.
+fn from_email_change_row<UserId>(
+    (hash, cancel_hash, user_id, old_email, new_email, expires_at): EmailChangeRow<UserId>,
+) -> EmailChangeRecord<UserId> {
+    EmailChangeRecord {
+        hash,
+        cancel_hash,
+        user_id,
+        old_email,
+        new_email,
+        expires_at: from_timestamp(expires_at),
+    }
+}
+
+/// Convert the session metadata columns to the metadata.
+fn from_metadata_row(
+    (user_agent, ip_address, created_at, last_active_at): MetadataRow,
+) -> SessionMetadata {
+    SessionMetadata {
+        user_agent,
+        ip_address: ip_address.and_then(|ip_address| ip_address.parse().ok()),
+        created_at: from_timestamp(created_at),
+        last_active_at: from_timestamp(last_active_at),
+    }
+}
+
+/// Concatenate the parts of a query.
+///
+/// Unlike [`concat!`], the parts are not separated by commas,
+/// which reads more like the query itself.
+macro_rules! sql {
+    ($($part:literal)*) => {
+        concat!($($part),*)
+    };
+}
+
+/// Implement the stores for a database.
+///
+/// The queries are shared by all databases,
+/// except for the syntax of their placeholders
+/// and the clause to skip inserting a session that already exists.
+macro_rules! impl_stores {
+    (
+        $db:ty,
+        placeholders = [
+            $p1:literal,
+            $p2:literal,
+            $p3:literal,
+            $p4:literal,
+            $p5:literal,
+            $p6:literal,
+            $p7:literal,
+            $p8:literal,
+            $p9:literal $(,)?
+        ],
+        on_conflict_do_nothing = $on_conflict_do_nothing:literal $(,)?
+    ) => {
+        impl<UserId, Data> crate::SessionStore
+            for SqlxSessionStore<$db, UserId, Data>
+        where
+            UserId:
+                for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db> + Type<$db>,
+            UserId: Clone + PartialEq + std::fmt::Debug + Send + Sync + Unpin,
+            Data: for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db> + Type<$db>,
+            Data: Default + Send + Sync + Unpin,
+        {
+            type UserId = UserId;
+            type SessionData = Data;
+            type Error = Error;
+
+            async fn load_session_data(
+                &self,
+                id: &SessionId,
+            ) -> Result<Option<crate::SessionFields<UserId, Data>>, Error> {
+                let row: Option<SessionRow<UserId, Data>> =
+                    sqlx::query_as(sql!(
+                        "SELECT user_id, data, version, authenticated_at, "
+                        "impersonator_id, user_agent, ip_address, "
+                        "created_at, last_active_at "
+                        "FROM autho_session WHERE id = " $p1
+                    ))
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+                Ok(row.map(
+                    |(
+                        user_id,
+                        data,
+                        version,
+                        authenticated_at,
+                        impersonator,
+                        user_agent,
+                        ip_address,
+                        created_at,
+                        last_active_at,
+                    )| {
+                        crate::SessionFields {
+                            user_id,
+                            data,
+                            version: version as u64,
+                            authenticated_at: authenticated_at
+                                .map(from_timestamp),
+                            impersonator,
+                            metadata: from_metadata_row((
+                                user_agent,
+                                ip_address,
+                                created_at,
+                                last_active_at,
+                            )),
+                        }
+                    },
+                ))
+            }
+
+            async fn create_session_data(&self) -> Result<Data, Error> {
+                Ok(Data::default())
+            }
+
+            async fn update_session_data(
+                &self,
+                update: crate::SessionUpdate<'_, UserId, Data>,
+            ) -> Result<bool, Error> {
+                let crate::SessionUpdate {
+                    id,
+                    version,
+                    user_id,
+                    authenticated_at,
+                    impersonator,
+                    metadata,
+                    data,
+                } = update;
+                let authenticated_at = authenticated_at.map(to_timestamp);
+                let ip_address = metadata
+                    .ip_address
+                    .map(|ip_address| ip_address.to_string());
+                let result = if version == 0 {
+                    let result = sqlx::query(sql!(
+                        "INSERT INTO autho_session "
+                        "(id, user_id, authenticated_at, impersonator_id, "
+                        "user_agent, ip_address, created_at, last_active_at, "
+                        "data, version) "
+                        "VALUES (" $p1 ", " $p2 ", " $p3 ", " $p4 ", " $p5 ", "
+                        $p6 ", " $p7 ", " $p8 ", " $p9 ", 1)"
+                        $on_conflict_do_nothing
+                    ))
+                    .bind(id)
+                    .bind(user_id)
+                    .bind(authenticated_at)
+                    .bind(impersonator)
+                    .bind(&metadata.user_agent)
+                    .bind(ip_address)
+                    .bind(to_timestamp(metadata.created_at))
+                    .bind(to_timestamp(metadata.last_active_at))
+                    .bind(data)
+                    .execute(&self.pool)
+                    .await;
+                    match result {
+                        Err(sqlx::Error::Database(e))
+                            if e.is_unique_violation() =>
+                        {
+                            return Ok(false);
+                        }
+                        result => result?,
+                    }
+                } else {
+                    sqlx::query(sql!(
+                        "UPDATE autho_session "
+                        "SET user_id = " $p1 ", authenticated_at = " $p2 ", "
+                        "impersonator_id = " $p3 ", user_agent = " $p4 ", "
+                        "ip_address = " $p5 ", last_active_at = " $p6 ", "
+                        "data = " $p7 ", "
+                        "version = version + 1 "
+                        "WHERE id = " $p8 " AND version = " $p9
+                    ))
+                    .bind(user_id)
+                    .bind(authenticated_at)
+                    .bind(impersonator)
+                    .bind(&metadata.user_agent)
+                    .bind(ip_address)
+                    .bind(to_timestamp(metadata.last_active_at))
+                    .bind(data)
+                    .bind(id)
+                    .bind(version as i64)
+                    .execute(&self.pool)
+                    .await?
+                };
+                Ok(result.rows_affected() == 1)
+            }
+
+            async fn delete_session_data(
+                &self,
+                id: &SessionId,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "DELETE FROM autho_session WHERE id = " $p1
+                ))
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+        }
+
+        impl<UserId, Data> crate::UserSessionsStore
+            for SqlxSessionStore<$db, UserId, Data>
+        where
+            UserId:
+                for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db> + Type<$db>,
+            UserId: Clone + PartialEq + std::fmt::Debug + Send + Sync + Unpin,
+            Data: for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db> + Type<$db>,
+            Data: Default + Send + Sync + Unpin,
+        {
+            async fn delete_user_sessions(
+                &self,
+                user_id: &UserId,
+                except: &SessionId,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "DELETE FROM autho_session "
+                    "WHERE user_id = " $p1 " AND id <> " $p2
+                ))
+                .bind(user_id)
+                .bind(except)
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+
+            async fn list_user_sessions(
+                &self,
+                user_id: &UserId,
+            ) -> Result<Vec<SessionInfo>, Error> {
+                let rows: Vec<(
+                    SessionId,
+                    Option<String>,
+                    Option<String>,
+                    i64,
+                    i64,
+                )> = sqlx::query_as(sql!(
+                    "SELECT id, user_agent, ip_address, "
+                    "created_at, last_active_at "
+                    "FROM autho_session WHERE user_id = " $p1 " "
+                    "ORDER BY last_active_at DESC"
+                ))
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?;
+                Ok(rows
+                    .into_iter()
+                    .map(
+                        |(
+                            id,
+                            user_agent,
+                            ip_address,
+                            created_at,
+                            last_active_at,
+                        )| SessionInfo {
+                            id,
+                            metadata: from_metadata_row((
+                                user_agent,
+                                ip_address,
+                                created_at,
+                                last_active_at,
+                            )),
+                        },
+                    )
+                    .collect())
+            }
+
+            async fn delete_user_session(
+                &self,
+                user_id: &UserId,
+                id: &SessionId,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "DELETE FROM autho_session "
+                    "WHERE id = " $p1 " AND user_id = " $p2
+                ))
+                .bind(id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+        }
+
+        impl<U> crate::UserStore for SqlxUserStore<$db, U>
+        where
+            U: User + for<'r> FromRow<'r, <$db as Database>::Row>,
+            U: Send + Unpin,
+            U::Id: for<'q> Encode<'q, $db> + Type<$db> + Sync,
+        {
+            type User = U;
+            type Error = Error;
+
+            async fn load_user(&self, id: &U::Id) -> Result<Option<U>, Error> {
+                Ok(sqlx::query_as(sql!(
+                    "SELECT * FROM autho_user WHERE id = " $p1
+                ))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?)
+            }
+
+            async fn load_user_by_email(
+                &self,
+                email: &str,
+            ) -> Result<Option<U>, Error> {
+                Ok(sqlx::query_as(sql!(
+                    "SELECT * FROM autho_user WHERE email = " $p1
+                ))
+                .bind(email)
+                .fetch_optional(&self.pool)
+                .await?)
+            }
+
+            async fn update_user_password(
+                &self,
+                id: &U::Id,
+                hashed_password: &HashedPassword,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "UPDATE autho_user "
+                    "SET hashed_password = " $p1 " WHERE id = " $p2
+                ))
+                .bind(hashed_password)
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+
+            async fn load_password_history(
+                &self,
+                id: &U::Id,
+                limit: usize,
+            ) -> Result<Vec<HashedPassword>, Error> {
+                let rows: Vec<(HashedPassword,)> =
+                    sqlx::query_as(sql!(
+                        "SELECT hashed_password "
+                        "FROM autho_password_history WHERE user_id = " $p1 " "
+                        "ORDER BY id DESC LIMIT " $p2
+                    ))
+                    .bind(id)
+                    .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+                    .fetch_all(&self.pool)
+                    .await?;
+                Ok(rows
+                    .into_iter()
+                    .map(|(hashed_password,)| hashed_password)
+                    .collect())
+            }
+
+            async fn add_password_history(
+                &self,
+                id: &U::Id,
+                hashed_password: &HashedPassword,
+                keep: usize,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "INSERT INTO autho_password_history "
+                    "(user_id, hashed_password) VALUES (" $p1 ", " $p2 ")"
+                ))
+                .bind(id)
+                .bind(hashed_password)
+                .execute(&self.pool)
+                .await?;
+                sqlx::query(sql!(
+                    // NOTE: MySQL does not support LIMIT in IN subqueries,
+                    // nor selecting from the table being deleted from,
+                    // unless the subquery is wrapped in a derived table.
+                    "DELETE FROM autho_password_history "
+                    "WHERE user_id = " $p1 " AND id NOT IN (SELECT id FROM "
+                    "(SELECT id FROM autho_password_history "
+                    "WHERE user_id = " $p2 " "
+                    "ORDER BY id DESC LIMIT " $p3 ") AS recent)"
+                ))
+                .bind(id)
+                .bind(id)
+                .bind(i64::try_from(keep).unwrap_or(i64::MAX))
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+        }
+
+        impl<U> crate::RegistrationStore for SqlxUserStore<$db, U>
+        where
+            U: User + for<'r> FromRow<'r, <$db as Database>::Row>,
+            U: Send + Unpin,
+            U::Id: for<'q> Encode<'q, $db> + Type<$db> + Sync,
+        {
+            async fn create_user(
+                &self,
+                email: &str,
+                hashed_password: Option<&HashedPassword>,
+            ) -> Result<Option<U>, Error> {
+                let result = sqlx::query(sql!(
+                    "INSERT INTO autho_user (email, hashed_password) "
+                    "VALUES (" $p1 ", " $p2 ")"
+                ))
+                .bind(email)
+                .bind(hashed_password)
+                .execute(&self.pool)
+                .await;
+                match result {
+                    Err(sqlx::Error::Database(e))
+                        if e.is_unique_violation() =>
+                    {
+                        return Ok(None);
+                    }
+                    result => result?,
+                };
+                crate::UserStore::load_user_by_email(self, email).await
+            }
+        }
+
+        impl<U> SqlxUserStore<$db, U>
+        where
+            U: User + for<'r> FromRow<'r, <$db as Database>::Row>,
+            U: Send + Unpin,
+            U::Id: for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db>,
+            U::Id: Type<$db> + Sync + Unpin,
+        {
+            /// Delete a pending email change once it has been loaded,
+            /// returning it only if it was not deleted concurrently.
+            async fn delete_email_change(
+                &self,
+                row: Option<EmailChangeRow<U::Id>>,
+            ) -> Result<Option<EmailChangeRecord<U::Id>>, Error> {
+                let Some(row) = row else {
+                    return Ok(None);
+                };
+                let result = sqlx::query(sql!(
+                    "DELETE FROM autho_email_change WHERE hash = " $p1
+                ))
+                .bind(row.0)
+                .execute(&self.pool)
+                .await?;
+                Ok((result.rows_affected() == 1)
+                    .then(|| from_email_change_row(row)))
+            }
+        }
+
+        impl<U> crate::EmailChangeStore for SqlxUserStore<$db, U>
+        where
+            U: User + for<'r> FromRow<'r, <$db as Database>::Row>,
+            U: Send + Unpin,
+            U::Id: for<'q> Encode<'q, $db> + for<'r> Decode<'r, $db>,
+            U::Id: Type<$db> + Sync + Unpin,
+        {
+            async fn store_email_change(
+                &self,
+                change: &EmailChangeRecord<U::Id>,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "INSERT INTO autho_email_change "
+                    "(hash, cancel_hash, user_id, "
+                    "old_email, new_email, expires_at) "
+                    "VALUES (" $p1 ", " $p2 ", " $p3 ", "
+                    $p4 ", " $p5 ", " $p6 ")"
+                ))
+                .bind(change.hash)
+                .bind(change.cancel_hash)
+                .bind(&change.user_id)
+                .bind(&change.old_email)
+                .bind(&change.new_email)
+                .bind(to_timestamp(change.expires_at))
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+
+            async fn take_email_change(
+                &self,
+                hash: &EmailChangeTokenHash,
+            ) -> Result<Option<EmailChangeRecord<U::Id>>, Error> {
+                let row = sqlx::query_as(sql!(
+                    "SELECT hash, cancel_hash, user_id, "
+                    "old_email, new_email, expires_at "
+                    "FROM autho_email_change WHERE hash = " $p1
+                ))
+                .bind(hash)
+                .fetch_optional(&self.pool)
+                .await?;
+                self.delete_email_change(row).await
+            }
+
+            async fn take_email_change_by_cancel_hash(
+                &self,
+                cancel_hash: &EmailChangeTokenHash,
+            ) -> Result<Option<EmailChangeRecord<U::Id>>, Error> {
+                let row = sqlx::query_as(sql!(
+                    "SELECT hash, cancel_hash, user_id, "
+                    "old_email, new_email, expires_at "
+                    "FROM autho_email_change WHERE cancel_hash = " $p1
+                ))
+                .bind(cancel_hash)
+                .fetch_optional(&self.pool)
+                .await?;
+                self.delete_email_change(row).await
+            }
+
+            async fn update_user_email(
+                &self,
+                id: &U::Id,
+                email: &str,
+            ) -> Result<bool, Error> {
+                let result = sqlx::query(sql!(
+                    "UPDATE autho_user SET email = " $p1 " WHERE id = " $p2
+                ))
+                .bind(email)
+                .bind(id)
+                .execute(&self.pool)
+                .await;
+                match result {
+                    Err(sqlx::Error::Database(e))
+                        if e.is_unique_violation() =>
+                    {
+                        Ok(false)
+                    }
+                    result => Ok(result?.rows_affected() == 1),
+                }
+            }
+
+            async fn delete_user_email_changes(
+                &self,
+                user_id: &U::Id,
+            ) -> Result<(), Error> {
+                sqlx::query(sql!(
+                    "DELETE FROM autho_email_change "
+                    "WHERE user_id = " $p1
+                ))
+                .bind(user_id)
+                .execute(&self.pool)
+                .await?;
+                Ok(())
+            }
+        }
+    };
+}
+
+#[cfg(feature = "sqlx-sqlite")]
+impl_stores!(
+    sqlx::Sqlite,
+    placeholders = ["$1", "$2", "$3", "$4", "$5", "$6", "$7", "$8", "$9"],
+    on_conflict_do_nothing = " ON CONFLICT (id) DO NOTHING",
+);
+
+#[cfg(feature = "sqlx-postgres")]
+impl_stores!(
+    sqlx::Postgres,
+    placeholders = ["$1", "$2", "$3", "$4", "$5", "$6", "$7", "$8", "$9"],
+    on_conflict_do_nothing = " ON CONFLICT (id) DO NOTHING",
+);
+
+#[cfg(feature = "sqlx-mysql")]
+impl_stores!(
+    sqlx::MySql,
+    placeholders = ["?", "?", "?", "?", "?", "?", "?", "?", "?"],
+    // NOTE: MySQL has no equivalent of `ON CONFLICT DO NOTHING`;
+    // `ON DUPLICATE KEY UPDATE` reports duplicates as affected rows
+    // with `CLIENT_FOUND_ROWS`, and `INSERT IGNORE` ignores other errors too,
+    // so the duplicate key error is handled instead.
+    on_conflict_do_nothing = "",
+);
+
+/// The migrations creating the tables used by the SQLite stores.
+#[cfg(feature = "sqlx-sqlite")]
+pub static SQLITE_MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate::Migrator {
+    ignore_missing: true,
+    ..sqlx::migrate!("migrations/sqlite")
+};
+
+/// The migrations creating the tables used by the PostgreSQL stores.
+#[cfg(feature = "sqlx-postgres")]
+pub static POSTGRES_MIGRATOR: sqlx::migrate::Migrator =
+    sqlx::migrate::Migrator {
+        ignore_missing: true,
+        ..sqlx::migrate!("migrations/postgres")
+    };
+
+/// The migrations creating the tables used by the MySQL stores.
+#[cfg(feature = "sqlx-mysql")]
+pub static MYSQL_MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate::Migrator {
+    ignore_missing: true,
+    ..sqlx::migrate!("migrations/mysql")
+};
+
+impl<DB: Database> Type<DB> for HashedPassword
+where
+    str: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <str as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <str as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database> Encode<'q, DB> for HashedPassword
+where
+    String: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        <String as Encode<'q, DB>>::encode(self.as_str().to_owned(), buf)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for HashedPassword
+where
+    &'r str: Decode<'r, DB>,
+{
+    fn decode(
+        value: <DB as Database>::ValueRef<'r>,
+    ) -> Result<Self, BoxDynError> {
+        Ok(<&str as Decode<'r, DB>>::decode(value)?.parse()?)
+    }
+}
+
+impl<DB: Database> Type<DB> for SessionId
+where
+    Vec<u8>: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <Vec<u8> as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <Vec<u8> as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database> Encode<'q, DB> for SessionId
+where
+    Vec<u8>: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        <Vec<u8> as Encode<'q, DB>>::encode(self.0.to_vec(), buf)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for SessionId
+where
+    Vec<u8>: Decode<'r, DB>,
+{
+    fn decode(
+        value: <DB as Database>::ValueRef<'r>,
+    ) -> Result<Self, BoxDynError> {
+        let bytes = <Vec<u8> as Decode<'r, DB>>::decode(value)?;
+        Ok(Self(bytes.try_into().map_err(|_| "invalid session id")?))
+    }
+}
+
+impl<DB: Database> Type<DB> for EmailChangeTokenHash
+where
+    Vec<u8>: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <Vec<u8> as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <Vec<u8> as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database> Encode<'q, DB> for EmailChangeTokenHash
+where
+    Vec<u8>: Encode<'q, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as Database>::ArgumentBuffer<'q>,
+    ) -> Result<IsNull, BoxDynError> {
+        <Vec<u8> as Encode<'q, DB>>::encode(self.0.to_vec(), buf)
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for EmailChangeTokenHash
+where
+    Vec<u8>: Decode<'r, DB>,
+{
+    fn decode(
+        value: <DB as Database>::ValueRef<'r>,
+    ) -> Result<Self, BoxDynError> {
+        let bytes = <Vec<u8> as Decode<'r, DB>>::decode(value)?;
+        Ok(Self(bytes.try_into().map_err(|_| "invalid token hash")?))
+    }
+}
+
+#[cfg(all(test, feature = "sqlx-sqlite"))]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::{
+        Backend, EmailChangeBackend, PasswordPolicy, RegistrationBackend,
+        Session, SessionToken, UserSessionsBackend, ValidPassword,
+    };
+
+    #[derive(FromRow, Clone, Debug)]
+    struct TestUser {
+        id: i64,
+        email: String,
+        hashed_password: Option<HashedPassword>,
+    }
+
+    impl User for TestUser {
+        type Id = i64;
+
+        fn id(&self) -> &i64 {
+            &self.id
+        }
+
+        fn email(&self) -> &str {
+            &self.email
+        }
+
+        fn hashed_password(&self) -> Option<&HashedPassword> {
+            self.hashed_password.as_ref()
+        }
+    }
+
+    type TestBackend = SqlxBackend<sqlx::Sqlite, TestUser, String>;
+
+    async fn backend() -> TestBackend {
+        // NOTE: Each connection to `sqlite::memory:` opens its own database.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        SQLITE_MIGRATOR.run(&pool).await.unwrap();
+        SqlxBackend::from_pool(pool)
+    }
+
+    async fn hash(password: &str) -> HashedPassword {
+        let policy = PasswordPolicy::default();
+        let password = ValidPassword::new(password.to_owned(), &policy, &[])
+            .await
+            .unwrap();
+        HashedPassword::new(&password)
+    }
+
+    async fn save(
+        backend: &TestBackend,
+        id: &SessionId,
+        version: u64,
+        user_id: Option<&i64>,
+        data: &str,
+    ) -> bool {
+        backend
+            .update_session_data(crate::SessionUpdate {
+                id,
+                version,
+                user_id,
+                authenticated_at: None,
+                impersonator: None,
+                metadata: &SessionMetadata::new(),
+                data: &data.to_owned(),
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn create_session() {
+        let backend = backend().await;
+        let id = SessionToken::generate(32).id();
+        assert!(backend.load_session_data(&id).await.unwrap().is_none());
+        assert!(save(&backend, &id, 0, None, "data").await);
+        let fields = backend.load_session_data(&id).await.unwrap().unwrap();
+        assert_eq!(fields.version, 1);
+        assert_eq!(fields.data, "data");
+        assert_eq!(fields.user_id, None);
+    }
+
+    #[tokio::test]
+    async fn create_session_conflict() {
+        let backend = backend().await;
+        let id = SessionToken::generate(32).id();
+        assert!(save(&backend, &id, 0, None, "first").await);
+        assert!(!save(&backend, &id, 0, None, "second").await);
+        let fields = backend.load_session_data(&id).await.unwrap().unwrap();
+        assert_eq!(fields.version, 1);
+        assert_eq!(fields.data, "first");
+    }
+
+    #[tokio::test]
+    async fn update_session_compare_and_swap() {
+        let backend = backend().await;
+        let id = SessionToken::generate(32).id();
+        assert!(save(&backend, &id, 0, None, "first").await);
+        assert!(save(&backend, &id, 1, None, "second").await);
+        assert!(!save(&backend, &id, 1, None, "stale").await);
+        let fields = backend.load_session_data(&id).await.unwrap().unwrap();
+        assert_eq!(fields.version, 2);
+        assert_eq!(fields.data, "second");
+    }
+
+    #[tokio::test]
+    async fn update_missing_session() {
+        let backend = backend().await;
+        let id = SessionToken::generate(32).id();
+        assert!(!save(&backend, &id, 1, None, "data").await);
+        assert!(backend.load_session_data(&id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_session() {
+        let backend = backend().await;
+        let id = SessionToken::generate(32).id();
+        assert!(save(&backend, &id, 0, None, "data").await);
+        backend.delete_session_data(&id).await.unwrap();
+        assert!(backend.load_session_data(&id).await.unwrap().is_none());
+        backend.delete_session_data(&id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_user() {
+        let backend = backend().await;
+        let hashed_password = hash("correct horse battery").await;
+        let user = backend
+            .create_user("a@example.com", Some(&hashed_password))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.email, "a@example.com");
+        assert!(
+            user.hashed_password
+                .unwrap()
+                .verify("correct horse battery")
+                .is_some()
+        );
+        let loaded = backend.load_user(&user.id).await.unwrap().unwrap();
+        assert_eq!(loaded.email, "a@example.com");
+        assert!(
+            backend
+                .create_user("a@example.com", None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn authenticated_at_round_trip() {
+        let backend = backend().await;
+        let hashed_password = hash("correct horse battery").await;
+        let user = backend
+            .create_user("a@example.com", Some(&hashed_password))
+            .await
+            .unwrap()
+            .unwrap();
+        let fields = crate::SessionFields {
+            user_id: Some(user.id),
+            data: String::new(),
+            version: 0,
+            authenticated_at: None,
+            impersonator: None,
+            metadata: SessionMetadata::new(),
+        };
+        let mut session =
+            Session::new(backend.clone(), SessionToken::generate(32), fields);
+        let reauthenticate = session.reauthenticate("correct horse battery");
+        assert!(reauthenticate.await.unwrap().is_some());
+        session.save().await.unwrap();
+        let loaded = backend.load_session_data(session.id()).await.unwrap();
+        assert_eq!(
+            loaded.unwrap().authenticated_at,
+            session.authenticated_at()
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_user_sessions() {
+        let backend = backend().await;
+        let user = backend.create_user("a@example.com", None).await.unwrap();
+        let other = backend.create_user("b@example.com", None).await.unwrap();
+        let (user, other) = (user.unwrap().id, other.unwrap().id);
+        let ids: Vec<_> =
+            (0..3).map(|_| SessionToken::generate(32).id()).collect();
+        for id in &ids {
+            assert!(save(&backend, id, 0, Some(&user), "data").await);
+        }
+        let other_id = SessionToken::generate(32).id();
+        assert!(save(&backend, &other_id, 0, Some(&other), "data").await);
+        assert_eq!(backend.list_user_sessions(&user).await.unwrap().len(), 3);
+
+        backend.delete_user_sessions(&user, &ids[0]).await.unwrap();
+        let sessions = backend.list_user_sessions(&user).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, ids[0]);
+        assert!(backend.load_session_data(&ids[1]).await.unwrap().is_none());
+        assert!(
+            backend
+                .load_session_data(&other_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        // A session of another user is not deleted.
+        backend.delete_user_session(&user, &other_id).await.unwrap();
+        assert!(
+            backend
+                .load_session_data(&other_id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        backend.delete_user_session(&user, &ids[0]).await.unwrap();
+        assert!(backend.list_user_sessions(&user).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn password_history() {
+        let backend = backend().await;
+        let user = backend.create_user("a@example.com", None).await.unwrap();
+        let user = user.unwrap().id;
+        assert!(
+            backend
+                .load_password_history(&user, 5)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        for password in ["first password", "second password", "third password"]
+        {
+            let hashed_password = hash(password).await;
+            backend
+                .add_password_history(&user, &hashed_password, 2)
+                .await
+                .unwrap();
+        }
+        let history = backend.load_password_history(&user, 5).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history[0].verify("third password").is_some());
+        assert!(history[1].verify("second password").is_some());
+        let history = backend.load_password_history(&user, 1).await.unwrap();
+        assert_eq!(history.len(), 1);
+
+        let hashed_password = hash("fourth password").await;
+        backend
+            .add_password_history(&user, &hashed_password, 0)
+            .await
+            .unwrap();
+        assert!(
+            backend
+                .load_password_history(&user, 5)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn email_change() {
+        let backend = backend().await;
+        let user = backend.create_user("a@example.com", None).await.unwrap();
+        let other = backend.create_user("b@example.com", None).await.unwrap();
+        let (user, other) = (user.unwrap().id, other.unwrap().id);
+        let change = |n: u8, user_id| EmailChangeRecord {
+            hash: EmailChangeTokenHash([n; 32]),
+            cancel_hash: EmailChangeTokenHash([n + 100; 32]),
+            user_id,
+            old_email: "a@example.com".to_owned(),
+            new_email: "c@example.com".to_owned(),
+            expires_at: SystemTime::now(),
+        };
+        for (n, user_id) in [(1, user), (2, user), (3, other)] {
+            backend
+                .store_email_change(&change(n, user_id))
+                .await
+                .unwrap();
+        }
+        let taken = backend
+            .take_email_change(&EmailChangeTokenHash([1; 32]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(taken.user_id, user);
+        assert_eq!(taken.cancel_hash, EmailChangeTokenHash([101; 32]));
+        assert!(
+            backend
+                .take_email_change(&EmailChangeTokenHash([1; 32]))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        backend.delete_user_email_changes(&user).await.unwrap();
+        assert!(
+            backend
+                .take_email_change_by_cancel_hash(&EmailChangeTokenHash(
+                    [102; 32]
+                ))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            backend
+                .take_email_change_by_cancel_hash(&EmailChangeTokenHash(
+                    [103; 32]
+                ))
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn update_user_email() {
+        let backend = backend().await;
+        let user = backend.create_user("a@example.com", None).await.unwrap();
+        let other = backend.create_user("b@example.com", None).await.unwrap();
+        let (user, other) = (user.unwrap().id, other.unwrap().id);
+        assert!(
+            backend
+                .update_user_email(&user, "c@example.com")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            backend.load_user(&user).await.unwrap().unwrap().email,
+            "c@example.com",
+        );
+        assert!(
+            !backend
+                .update_user_email(&other, "c@example.com")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !backend
+                .update_user_email(&(other + 1), "d@example.com")
+                .await
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,658 @@
+//! An in-memory backend for tests.
+
+// NOTE: Which helpers are used depends on the enabled features.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use crate::{
+    AccountStatus, ApiTokenBackend, ApiTokenHash, ApiTokenRecord, AuditEvent,
+    Backend, EmailChangeBackend, EmailChangeRecord, EmailChangeTokenHash,
+    HashedPassword, LoginTokenHash, LoginTokenRecord, MagicLinkBackend,
+    RememberMeBackend, RememberMeHash, RememberMeRecord, Session,
+    SessionFields, SessionId, SessionInfo, SessionMetadata, SessionToken,
+    SessionUpdate, User, UserSessionsBackend,
+};
+
+/// A user stored by the [`TestBackend`].
+#[derive(Clone, Debug)]
+pub struct TestUser {
+    pub id: u64,
+    pub email: String,
+    pub hashed_password: Option<HashedPassword>,
+    pub status: AccountStatus,
+}
+
+impl User for TestUser {
+    type Id = u64;
+
+    fn id(&self) -> &u64 {
+        &self.id
+    }
+
+    fn email(&self) -> &str {
+        &self.email
+    }
+
+    fn hashed_password(&self) -> Option<&HashedPassword> {
+        self.hashed_password.as_ref()
+    }
+
+    fn account_status(&self) -> AccountStatus {
+        self.status
+    }
+
+    fn set_hashed_password(&mut self, hashed_password: Option<HashedPassword>) {
+        self.hashed_password = hashed_password;
+    }
+}
+
+/// The session data of the [`TestBackend`].
+#[derive(Clone, Default, Debug)]
+pub struct TestData {
+    #[cfg(feature = "passkey")]
+    pub passkey_challenge: Option<crate::passkey::PasskeyChallenge>,
+    #[cfg(feature = "oidc")]
+    pub oidc_state: Option<crate::oidc::OidcState>,
+}
+
+#[cfg(feature = "passkey")]
+impl crate::passkey::PasskeySessionData for TestData {
+    fn passkey_challenge(
+        &mut self,
+    ) -> &mut Option<crate::passkey::PasskeyChallenge> {
+        &mut self.passkey_challenge
+    }
+}
+
+#[cfg(feature = "oidc")]
+impl crate::oidc::OidcSessionData for TestData {
+    fn oidc_state(&mut self) -> &mut Option<crate::oidc::OidcState> {
+        &mut self.oidc_state
+    }
+}
+
+/// Everything stored by the [`TestBackend`].
+#[derive(Default)]
+pub struct TestState {
+    pub sessions: HashMap<SessionId, SessionFields<u64, TestData>>,
+    pub users: Vec<TestUser>,
+    pub remember_me_tokens: Vec<RememberMeRecord<u64>>,
+    pub login_tokens: Vec<LoginTokenRecord<u64>>,
+    pub email_changes: Vec<EmailChangeRecord<u64>>,
+    pub api_tokens: Vec<ApiTokenRecord<u64>>,
+    pub audit_events: Vec<AuditEvent<u64>>,
+    #[cfg(feature = "passkey")]
+    pub passkeys: Vec<crate::passkey::PasskeyCredential<u64>>,
+    #[cfg(feature = "oidc")]
+    pub identities: Vec<(u64, crate::oidc::OidcIdentity)>,
+    #[cfg(feature = "jwt")]
+    pub refresh_tokens: Vec<crate::jwt::RefreshTokenRecord<u64>>,
+}
+
+/// A backend storing everything in memory.
+///
+/// Clones share the same state.
+#[derive(Clone, Default)]
+pub struct TestBackend(Arc<Mutex<TestState>>);
+
+impl TestBackend {
+    pub fn state(&self) -> MutexGuard<'_, TestState> {
+        self.0.lock().unwrap()
+    }
+
+    /// Add an active user without a password.
+    pub fn add_user(&self, email: &str) -> u64 {
+        let mut state = self.state();
+        let id = state.users.len() as u64 + 1;
+        state.users.push(TestUser {
+            id,
+            email: email.to_owned(),
+            hashed_password: None,
+            status: AccountStatus::Active,
+        });
+        id
+    }
+
+    /// Create a new session, with the given user (if any) logged in.
+    pub fn session(&self, user_id: Option<u64>) -> Session<Self> {
+        self.session_authenticated_at(
+            user_id,
+            user_id.map(|_| SystemTime::now()),
+        )
+    }
+
+    /// Create a session for the given user, who authenticated at the given time.
+    pub fn session_authenticated_at(
+        &self,
+        user_id: Option<u64>,
+        authenticated_at: Option<SystemTime>,
+    ) -> Session<Self> {
+        Session::new(
+            self.clone(),
+            SessionToken::generate(32),
+            SessionFields {
+                user_id,
+                data: TestData::default(),
+                version: 0,
+                authenticated_at,
+                impersonator: None,
+                metadata: SessionMetadata::new(),
+            },
+        )
+    }
+}
+
+impl Backend for TestBackend {
+    type User = TestUser;
+    type SessionData = TestData;
+    type Error = Infallible;
+
+    async fn load_session_data(
+        &self,
+        id: &SessionId,
+    ) -> Result<Option<SessionFields<u64, TestData>>, Infallible> {
+        Ok(self.state().sessions.get(id).cloned())
+    }
+
+    async fn create_session_data(&self) -> Result<TestData, Infallible> {
+        Ok(TestData::default())
+    }
+
+    async fn update_session_data(
+        &self,
+        update: SessionUpdate<'_, u64, TestData>,
+    ) -> Result<bool, Infallible> {
+        let mut state = self.state();
+        let current = state
+            .sessions
+            .get(update.id)
+            .map_or(0, |fields| fields.version);
+        if current != update.version {
+            return Ok(false);
+        }
+        state.sessions.insert(
+            *update.id,
+            SessionFields {
+                user_id: update.user_id.copied(),
+                data: update.data.clone(),
+                version: update.version + 1,
+                authenticated_at: update.authenticated_at,
+                impersonator: update.impersonator.copied(),
+                metadata: update.metadata.clone(),
+            },
+        );
+        Ok(true)
+    }
+
+    async fn delete_session_data(
+        &self,
+        id: &SessionId,
+    ) -> Result<(), Infallible> {
+        self.state().sessions.remove(id);
+        Ok(())
+    }
+
+    async fn load_user(
+        &self,
+        id: &u64,
+    ) -> Result<Option<TestUser>, Infallible> {
+        let state = self.state();
+        Ok(state.users.iter().find(|user| user.id == *id).cloned())
+    }
+
+    async fn load_user_by_email(
+        &self,
+        email: &str,
+    ) -> Result<Option<TestUser>, Infallible> {
+        let state = self.state();
+        Ok(state.users.iter().find(|user| user.email == email).cloned())
+    }
+
+    async fn update_user_password(
+        &self,
+        id: &u64,
+        hashed_password: &HashedPassword,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        if let Some(user) = state.users.iter_mut().find(|user| user.id == *id) {
+            user.hashed_password = Some(hashed_password.clone());
+        }
+        Ok(())
+    }
+
+    async fn record_audit_event(
+        &self,
+        event: &AuditEvent<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().audit_events.push(event.clone());
+        Ok(())
+    }
+}
+
+impl UserSessionsBackend for TestBackend {
+    async fn delete_user_sessions(
+        &self,
+        user_id: &u64,
+        except: &SessionId,
+    ) -> Result<(), Infallible> {
+        self.state().sessions.retain(|id, fields| {
+            id == except || fields.user_id != Some(*user_id)
+        });
+        Ok(())
+    }
+
+    async fn list_user_sessions(
+        &self,
+        user_id: &u64,
+    ) -> Result<Vec<SessionInfo>, Infallible> {
+        let state = self.state();
+        Ok(state
+            .sessions
+            .iter()
+            .filter(|(_, fields)| fields.user_id == Some(*user_id))
+            .map(|(id, fields)| SessionInfo {
+                id: *id,
+                metadata: fields.metadata.clone(),
+            })
+            .collect())
+    }
+
+    async fn delete_user_session(
+        &self,
+        user_id: &u64,
+        id: &SessionId,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        if state
+            .sessions
+            .get(id)
+            .is_some_and(|fields| fields.user_id == Some(*user_id))
+        {
+            state.sessions.remove(id);
+        }
+        Ok(())
+    }
+}
+
+impl RememberMeBackend for TestBackend {
+    async fn store_remember_me_token(
+        &self,
+        token: &RememberMeRecord<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().remember_me_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn load_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> Result<Option<RememberMeRecord<u64>>, Infallible> {
+        let state = self.state();
+        Ok(state
+            .remember_me_tokens
+            .iter()
+            .find(|record| record.selector == selector)
+            .cloned())
+    }
+
+    async fn replace_remember_me_token(
+        &self,
+        hash: &RememberMeHash,
+        token: &RememberMeRecord<u64>,
+    ) -> Result<bool, Infallible> {
+        let mut state = self.state();
+        let Some(record) = state.remember_me_tokens.iter_mut().find(|record| {
+            record.selector == token.selector && record.hash == *hash
+        }) else {
+            return Ok(false);
+        };
+        *record = token.clone();
+        Ok(true)
+    }
+
+    async fn delete_remember_me_token(
+        &self,
+        selector: &str,
+    ) -> Result<(), Infallible> {
+        self.state()
+            .remember_me_tokens
+            .retain(|record| record.selector != selector);
+        Ok(())
+    }
+
+    async fn delete_user_remember_me_tokens(
+        &self,
+        user_id: &u64,
+    ) -> Result<(), Infallible> {
+        self.state()
+            .remember_me_tokens
+            .retain(|record| record.user_id != *user_id);
+        Ok(())
+    }
+}
+
+impl MagicLinkBackend for TestBackend {
+    async fn store_login_token(
+        &self,
+        token: &LoginTokenRecord<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().login_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn take_login_token(
+        &self,
+        hash: &LoginTokenHash,
+    ) -> Result<Option<LoginTokenRecord<u64>>, Infallible> {
+        let mut state = self.state();
+        let index = state
+            .login_tokens
+            .iter()
+            .position(|record| record.hash == *hash);
+        Ok(index.map(|index| state.login_tokens.remove(index)))
+    }
+
+    async fn delete_user_login_tokens(
+        &self,
+        user_id: &u64,
+    ) -> Result<(), Infallible> {
+        self.state()
+            .login_tokens
+            .retain(|record| record.user_id != *user_id);
+        Ok(())
+    }
+}
+
+impl ApiTokenBackend for TestBackend {
+    async fn store_api_token(
+        &self,
+        token: &ApiTokenRecord<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().api_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn load_api_token(
+        &self,
+        hash: &ApiTokenHash,
+    ) -> Result<Option<ApiTokenRecord<u64>>, Infallible> {
+        let state = self.state();
+        Ok(state.api_tokens.iter().find(|t| t.hash == *hash).cloned())
+    }
+
+    async fn list_api_tokens(
+        &self,
+        user_id: &u64,
+    ) -> Result<Vec<ApiTokenRecord<u64>>, Infallible> {
+        let state = self.state();
+        let tokens = state.api_tokens.iter().filter(|t| t.user_id == *user_id);
+        Ok(tokens.cloned().collect())
+    }
+
+    async fn update_api_token_last_used(
+        &self,
+        hash: &ApiTokenHash,
+        last_used_at: SystemTime,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        if let Some(token) =
+            state.api_tokens.iter_mut().find(|t| t.hash == *hash)
+        {
+            token.last_used_at = Some(last_used_at);
+        }
+        Ok(())
+    }
+
+    async fn revoke_api_token(
+        &self,
+        user_id: &u64,
+        hash: &ApiTokenHash,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        if let Some(token) = state
+            .api_tokens
+            .iter_mut()
+            .find(|t| t.user_id == *user_id && t.hash == *hash)
+        {
+            token.revoked = true;
+        }
+        Ok(())
+    }
+}
+
+impl EmailChangeBackend for TestBackend {
+    async fn store_email_change(
+        &self,
+        change: &EmailChangeRecord<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().email_changes.push(change.clone());
+        Ok(())
+    }
+
+    async fn take_email_change(
+        &self,
+        hash: &EmailChangeTokenHash,
+    ) -> Result<Option<EmailChangeRecord<u64>>, Infallible> {
+        let mut state = self.state();
+        let index = state
+            .email_changes
+            .iter()
+            .position(|change| change.hash == *hash);
+        Ok(index.map(|index| state.email_changes.remove(index)))
+    }
+
+    async fn take_email_change_by_cancel_hash(
+        &self,
+        cancel_hash: &EmailChangeTokenHash,
+    ) -> Result<Option<EmailChangeRecord<u64>>, Infallible> {
+        let mut state = self.state();
+        let index = state
+            .email_changes
+            .iter()
+            .position(|change| change.cancel_hash == *cancel_hash);
+        Ok(index.map(|index| state.email_changes.remove(index)))
+    }
+
+    async fn update_user_email(
+        &self,
+        id: &u64,
+        email: &str,
+    ) -> Result<bool, Infallible> {
+        let mut state = self.state();
+        if state.users.iter().any(|user| user.email == email) {
+            return Ok(false);
+        }
+        let Some(user) = state.users.iter_mut().find(|user| user.id == *id)
+        else {
+            return Ok(false);
+        };
+        user.email = email.to_owned();
+        Ok(true)
+    }
+
+    async fn delete_user_email_changes(
+        &self,
+        user_id: &u64,
+    ) -> Result<(), Infallible> {
+        self.state()
+            .email_changes
+            .retain(|change| change.user_id != *user_id);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "passkey")]
+impl crate::passkey::PasskeyBackend for TestBackend {
+    async fn store_passkey(
+        &self,
+        credential: &crate::passkey::PasskeyCredential<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().passkeys.push(credential.clone());
+        Ok(())
+    }
+
+    async fn load_passkey(
+        &self,
+        credential_id: &[u8],
+    ) -> Result<Option<crate::passkey::PasskeyCredential<u64>>, Infallible>
+    {
+        let state = self.state();
+        Ok(state
+            .passkeys
+            .iter()
+            .find(|credential| credential.id == credential_id)
+            .cloned())
+    }
+
+    async fn list_passkeys(
+        &self,
+        user_id: &u64,
+    ) -> Result<Vec<crate::passkey::PasskeyCredential<u64>>, Infallible> {
+        let state = self.state();
+        Ok(state
+            .passkeys
+            .iter()
+            .filter(|credential| credential.user_id == *user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_passkey_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+        last_used_at: SystemTime,
+    ) -> Result<(), Infallible> {
+        let mut state = self.state();
+        if let Some(credential) = state
+            .passkeys
+            .iter_mut()
+            .find(|credential| credential.id == credential_id)
+        {
+            credential.sign_count = sign_count;
+            credential.last_used_at = Some(last_used_at);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "oidc")]
+impl crate::oidc::OidcBackend for TestBackend {
+    async fn load_user_by_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<TestUser>, Infallible> {
+        let state = self.state();
+        let user_id =
+            state.identities.iter().find_map(|(user_id, identity)| {
+                (identity.issuer == issuer && identity.subject == subject)
+                    .then_some(*user_id)
+            });
+        Ok(user_id.and_then(|user_id| {
+            state.users.iter().find(|user| user.id == user_id).cloned()
+        }))
+    }
+
+    async fn link_identity(
+        &self,
+        user_id: &u64,
+        identity: &crate::oidc::OidcIdentity,
+    ) -> Result<(), Infallible> {
+        self.state().identities.push((*user_id, identity.clone()));
+        Ok(())
+    }
+
+    async fn unlink_identity(
+        &self,
+        user_id: &u64,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<(), Infallible> {
+        self.state().identities.retain(|(id, identity)| {
+            !(id == user_id
+                && identity.issuer == issuer
+                && identity.subject == subject)
+        });
+        Ok(())
+    }
+
+    async fn list_identities(
+        &self,
+        user_id: &u64,
+    ) -> Result<Vec<crate::oidc::OidcIdentity>, Infallible> {
+        let state = self.state();
+        Ok(state
+            .identities
+            .iter()
+            .filter(|(id, _)| id == user_id)
+            .map(|(_, identity)| identity.clone())
+            .collect())
+    }
+}
+
+#[cfg(feature = "jwt")]
+impl crate::jwt::RefreshTokenBackend for TestBackend {
+    async fn store_refresh_token(
+        &self,
+        token: &crate::jwt::RefreshTokenRecord<u64>,
+    ) -> Result<(), Infallible> {
+        self.state().refresh_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn load_refresh_token(
+        &self,
+        hash: &crate::jwt::RefreshTokenHash,
+    ) -> Result<Option<crate::jwt::RefreshTokenRecord<u64>>, Infallible> {
+        let state = self.state();
+        Ok(state
+            .refresh_tokens
+            .iter()
+            .find(|t| t.hash == *hash)
+            .cloned())
+    }
+
+    async fn mark_refresh_token_used(
+        &self,
+        hash: &crate::jwt::RefreshTokenHash,
+    ) -> Result<bool, Infallible> {
+        let mut state = self.state();
+        let token = state.refresh_tokens.iter_mut().find(|t| t.hash == *hash);
+        match token {
+            Some(token) if !token.used => {
+                token.used = true;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_refresh_token_family(
+        &self,
+        family: &uuid::Uuid,
+    ) -> Result<(), Infallible> {
+        for token in &mut self.state().refresh_tokens {
+            if token.family == *family {
+                token.revoked = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn revoke_user_refresh_tokens(
+        &self,
+        user_id: &u64,
+    ) -> Result<(), Infallible> {
+        for token in &mut self.state().refresh_tokens {
+            if token.user_id == *user_id {
+                token.revoked = true;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Generate a random token, starting with `prefix`.
+///
+/// The token contains `N` random bytes, encoded as hex.
+pub fn random_token<const N: usize>(prefix: &str) -> String {
+    let mut token = String::with_capacity(prefix.len() + 2 * N);
+    token.push_str(prefix);
+    token.push_str(&random_hex(N));
+    token
+}
+
+/// Generate `n` random bytes, encoded as hex.
+pub fn random_hex(n: usize) -> String {
+    let mut bytes = vec![0; n];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let mut hex = String::with_capacity(2 * n);
+    for byte in bytes {
+        hex.push_str(&format!("{byte:02x}"));
+    }
+    hex
+}
+
+/// Hash a token using SHA-256.
+///
+/// Since tokens are long and random,
+/// a fast hash is sufficient to protect them at rest.
+pub fn sha256(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
@@ -2,6 +2,26 @@ use tokio::sync::OnceCell;
 
 use crate::{Backend, HashedPassword};
 
+/// The state of a user account.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AccountStatus {
+    /// The user can log in.
+    Active,
+    /// The account was disabled, for example because the user was banned.
+    Disabled,
+    /// The account was locked, for example after too many failed logins.
+    Locked,
+    /// The account is about to be deleted.
+    PendingDeletion,
+}
+
+impl AccountStatus {
+    /// Whether users with this status can log in.
+    pub fn is_active(self) -> bool {
+        self == Self::Active
+    }
+}
+
 /// The interface for a user.
 pub trait User: Send {
     /// The type used to identify a user.
@@ -16,6 +36,14 @@ pub trait User: Send {
     /// Get the hashed password of the user.
     fn hashed_password(&self) -> Option<&HashedPassword>;
 
+    /// Get the state of the account.
+    ///
+    /// Users that are not [active](AccountStatus::is_active) cannot log in,
+    /// and sessions they are logged into are treated as logged out.
+    fn account_status(&self) -> AccountStatus {
+        AccountStatus::Active
+    }
+
     /// Update the hashed password of this user.
     ///
     /// This only needs to be implemented in projects where
@@ -30,8 +58,9 @@ pub trait User: Send {
 pub struct SessionUser<U: User> {
     /// The id of the user.
     id: Option<U::Id>,
-    /// The user itself.
-    user: OnceCell<U>,
+    /// The user itself,
+    /// or `None` if they do not exist (anymore) or are not active.
+    user: OnceCell<Option<U>>,
 }
 
 impl<U: User> SessionUser<U> {
@@ -43,8 +72,11 @@ impl<U: User> SessionUser<U> {
     }
 
     /// Whether the user is authenticated.
+    ///
+    /// This is only `false` for missing or inactive users
+    /// once the user was loaded.
     pub fn is_authenticated(&self) -> bool {
-        self.id.is_some()
+        self.id.is_some() && !matches!(self.user.get(), Some(None))
     }
 
     pub fn id(&self) -> Option<&U::Id> {
@@ -62,43 +94,40 @@ impl<U: User> SessionUser<U> {
         &self,
         backend: &B,
     ) -> Result<Option<&U>, B::Error> {
-        enum Error<E> {
-            UserNotFound,
-            Inner(E),
-        }
         if let Some(id) = &self.id {
-            let result = self
+            let user = self
                 .user
-                .get_or_try_init(async || match backend.load_user(id).await {
-                    Ok(Some(user)) => Ok(user),
-                    Ok(None) => Err(Error::UserNotFound),
-                    Err(e) => Err(Error::Inner(e)),
+                .get_or_try_init(async || {
+                    let user = backend.load_user(id).await?;
+                    Ok(user.filter(|user| user.account_status().is_active()))
                 })
-                .await;
-            match result {
-                Ok(user) => Ok(Some(user)),
-                Err(Error::UserNotFound) => Ok(None),
-                Err(Error::Inner(e)) => Err(e),
-            }
+                .await?;
+            Ok(user.as_ref())
         } else {
             Ok(None)
         }
     }
 
-    pub async fn user_mut<B: Backend<User = U>>(
-        &mut self,
-        backend: &B,
-    ) -> Result<Option<&mut U>, B::Error> {
-        self.user(backend).await?;
-        Ok(self.user.get_mut())
+    /// Get the id of the user, if they were not loaded yet.
+    pub fn unloaded_id(&self) -> Option<&U::Id> {
+        self.id.as_ref().filter(|_| self.user.get().is_none())
+    }
+
+    /// Store the user loaded for [`unloaded_id()`](Self::unloaded_id).
+    pub fn set_loaded(&mut self, user: Option<U>) {
+        let user = user.filter(|user| user.account_status().is_active());
+        self.user = OnceCell::new_with(Some(user));
     }
 
     pub fn get_mut(&mut self) -> Option<&mut U> {
-        self.user.get_mut()
+        self.user.get_mut().and_then(Option::as_mut)
     }
 
     pub fn set_user(&mut self, user: Option<U>) {
         self.id = user.as_ref().map(|user| user.id().clone());
-        self.user = OnceCell::new_with(user);
+        self.user = match user {
+            Some(user) => OnceCell::new_with(Some(Some(user))),
+            None => OnceCell::new(),
+        };
     }
 }